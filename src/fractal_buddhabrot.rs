@@ -0,0 +1,91 @@
+use crate::fractal_complex::Complex;
+
+// Where the c are sampled, the whole Mandelbrot set fits in there
+const SAMPLE_RE: (f64, f64) = (-2.0, 1.0);
+const SAMPLE_IM: (f64, f64) = (-1.5, 1.5);
+
+// xorshift64*, plenty for sampling and no dependency needed
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // 0 would stay 0 forever
+        Self(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // In [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn next_c(&mut self) -> Complex<f64> {
+        Complex::new(
+            SAMPLE_RE.0 + self.next_f64() * (SAMPLE_RE.1 - SAMPLE_RE.0),
+            SAMPLE_IM.0 + self.next_f64() * (SAMPLE_IM.1 - SAMPLE_IM.0),
+        )
+    }
+}
+
+// The reverse of `Complex::map_pixel_value_f64`, from a point of an orbit to
+// its place in the histogram
+pub struct BuddhaView {
+    width: u32,
+    height: u32,
+    top_left: Complex<f64>,
+    window: Complex<f64>,
+}
+
+impl BuddhaView {
+    pub fn new(res: Complex<f64>, center: Complex<f64>, window: Complex<f64>) -> Self {
+        Self {
+            width: res.re as u32,
+            height: res.im as u32,
+            top_left: Complex::new(center.re - window.re / 2.0, center.im + window.im / 2.0),
+            window,
+        }
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    // Index of the pixel z falls in, if it is in the view
+    pub fn pixel_index(&self, z: Complex<f64>) -> Option<usize> {
+        let x = (z.re - self.top_left.re) / self.window.re * self.width as f64;
+        let y = (self.top_left.im - z.im) / self.window.im * self.height as f64;
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return None;
+        }
+        Some(y as usize * self.width as usize + x as usize)
+    }
+}
+
+// The histogram has 3 channels per pixel, every channel is scaled to its own
+// max and square rooted to bring up the orbits that don't go there often
+pub fn tone_map(hist: &[u64]) -> Vec<u8> {
+    let mut max = [1u64; 3];
+    for pixel in hist.chunks_exact(3) {
+        for (max, &count) in max.iter_mut().zip(pixel) {
+            *max = (*max).max(count);
+        }
+    }
+
+    let mut pixels = Vec::with_capacity(hist.len() / 3 * 4);
+    for pixel in hist.chunks_exact(3) {
+        for (&count, &max) in pixel.iter().zip(&max) {
+            let v = (count as f64 / max as f64).sqrt();
+            pixels.push((v * 255.0) as u8);
+        }
+        pixels.push(255);
+    }
+    pixels
+}
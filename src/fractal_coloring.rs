@@ -0,0 +1,252 @@
+use crate::{
+    fractal_complex::{self, Complex},
+    fractal_engine::{ColoringMode, FractalContext, OrbitTrap},
+    fractal_floatexp::FloatExp,
+};
+
+// Distance to the boundary (in pixels) under which the gradient gets darker
+const DE_DARKEN_PIXELS: f64 = 4.0;
+// How fast the trap gradient goes through its colors as the orbit gets further
+const TRAP_FALLOFF: f64 = 2.0;
+// The interior is colored by the trap too, dimmer so the set can still be seen
+const TRAP_INTERIOR_SHADE: f64 = 0.5;
+// How many stripes go around z for the stripe average
+const STRIPE_DENSITY: f64 = 5.0;
+
+// Everything needed to color a pixel, taken from the context once per reload
+#[derive(Clone, Copy, Default)]
+pub struct Coloring {
+    pub mode: ColoringMode,
+    pub seq_iter: u32,
+    pub bailout: f64,
+    pub smooth: bool,
+    pub pixel_size: FloatExp, // deeper than f64 goes with the perturbation
+    pub power: Option<u32>,
+    pub julia: bool,
+    pub trap: Option<OrbitTrap>, // Only with ColoringMode::OrbitTrap
+}
+
+// What is tracked along the orbit on top of z, only when the coloring mode needs it
+// z is always an f64 copy, no need for more precision to color
+pub struct OrbitStats {
+    z: Complex<f64>,
+    z_prev: Complex<f64>,
+    dz: Complex<FloatExp>, // dz/dc, or dz/dz_0 for the Julia sets, as small as the pixels
+    c: Complex<f64>,
+    mode: ColoringMode,
+    julia: bool,
+    power: Option<u32>,
+    trap: Option<OrbitTrap>,
+    trap_distance: f64, // closest the orbit got to the trap
+    avg_sum: f64,       // sum of the averaged statistic
+    avg_last: f64,      // its last term, to get the average without it
+    avg_count: u32,
+}
+
+impl Coloring {
+    pub fn from_ctx(ctx: &FractalContext) -> Self {
+        let power = ctx.power();
+        Self {
+            mode: match ctx.coloring_mode {
+                mode if power.is_none() && mode.needs_power() => ColoringMode::Iteration,
+                mode => mode,
+            },
+            seq_iter: ctx.seq_iter,
+            bailout: ctx.bailout(),
+            smooth: ctx.smooth_coloring && power.is_some(),
+            pixel_size: ctx.pixel_size_floatexp(),
+            power,
+            julia: ctx.julia,
+            trap: match ctx.coloring_mode {
+                ColoringMode::OrbitTrap => Some(ctx.orbit_trap),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn tracks_orbit(&self) -> bool {
+        self.mode != ColoringMode::Iteration
+    }
+
+    // The series approximation would skip some of the z these modes need
+    pub fn needs_every_z(&self) -> bool {
+        matches!(
+            self.mode,
+            ColoringMode::OrbitTrap
+                | ColoringMode::StripeAverage
+                | ColoringMode::TriangleAverage
+                | ColoringMode::CurvatureAverage
+        )
+    }
+
+    // The pixels that never escaped
+    pub fn interior(&self, stats: &OrbitStats) -> [u8; 4] {
+        match self.mode {
+            ColoringMode::OrbitTrap => {
+                let color = trap_color(stats.trap_distance);
+                [
+                    (color[0] as f64 * TRAP_INTERIOR_SHADE) as u8,
+                    (color[1] as f64 * TRAP_INTERIOR_SHADE) as u8,
+                    (color[2] as f64 * TRAP_INTERIOR_SHADE) as u8,
+                    255,
+                ]
+            }
+            _ => [0, 0, 0, 255],
+        }
+    }
+
+    pub fn escaped(&self, iter: u32, distance: f64, stats: &OrbitStats) -> [u8; 4] {
+        // Nothing to smooth with without a power, the averages aren't blended
+        let smooth_iter = match self.power {
+            Some(power) => fractal_complex::smooth_iter(iter, distance, self.bailout, power),
+            None => iter as f64,
+        };
+        let iter = match self.smooth {
+            true => smooth_iter,
+            false => iter as f64,
+        };
+        let color = fractal_complex::iter_gradient(iter, self.seq_iter);
+
+        match self.mode {
+            ColoringMode::Iteration => color,
+            ColoringMode::DistanceBoundary => {
+                let shade = stats.distance_estimate(self.pixel_size).clamp(0.0, 1.0);
+                let v = (shade * 255.0) as u8;
+                [v, v, v, 255]
+            }
+            ColoringMode::DistanceDarkened => {
+                let shade = (stats.distance_estimate(self.pixel_size) / DE_DARKEN_PIXELS)
+                    .clamp(0.0, 1.0)
+                    .sqrt();
+                [
+                    (color[0] as f64 * shade) as u8,
+                    (color[1] as f64 * shade) as u8,
+                    (color[2] as f64 * shade) as u8,
+                    255,
+                ]
+            }
+            ColoringMode::OrbitTrap => trap_color(stats.trap_distance),
+            ColoringMode::StripeAverage
+            | ColoringMode::TriangleAverage
+            | ColoringMode::CurvatureAverage => {
+                fractal_complex::iter_gradient(stats.average(smooth_iter.fract()), 1)
+            }
+        }
+    }
+}
+
+// The usual gradient, red when the orbit went right through the trap
+fn trap_color(trap_distance: f64) -> [u8; 4] {
+    let t = 1.0 - (-trap_distance * TRAP_FALLOFF).exp();
+    fractal_complex::iter_gradient(t, 1)
+}
+
+impl OrbitStats {
+    // Starts at the pixel : z_1 = c for the Mandelbrot set, z_0 for the Julia
+    // sets, where the derivative is 1 either way
+    pub fn new(z: Complex<f64>, c: Complex<f64>, coloring: &Coloring) -> Self {
+        let one = Complex::new(FloatExp::from_f64(1.0), FloatExp::ZERO);
+        Self::starting_at(z, one, c, coloring)
+    }
+
+    // For when the first iterations were skipped (series approximation)
+    pub fn starting_at(
+        z: Complex<f64>,
+        dz: Complex<FloatExp>,
+        c: Complex<f64>,
+        coloring: &Coloring,
+    ) -> Self {
+        Self {
+            z,
+            z_prev: z,
+            dz,
+            c,
+            mode: coloring.mode,
+            julia: coloring.julia,
+            power: coloring.power,
+            trap: coloring.trap,
+            trap_distance: coloring.trap.map_or(f64::INFINITY, |trap| trap.distance(z)),
+            avg_sum: 0.0,
+            avg_last: 0.0,
+            avg_count: 0,
+        }
+    }
+
+    // Called with every new z, after z = f(z) + c
+    // Burning Ship & co aren't holomorphic, z² + c is close enough for them
+    pub fn update(&mut self, z: Complex<f64>) {
+        // dz' = n*z^(n-1)*dz (+ 1), with the z from before the iteration
+        // Only the distance estimation needs it and FloatExp isn't free
+        if let (ColoringMode::DistanceBoundary | ColoringMode::DistanceDarkened, Some(power)) =
+            (self.mode, self.power)
+        {
+            let factor = Complex::new(power as f64, 0.0) * self.z.powu(power - 1);
+            self.dz = Complex::from_f64_floatexp(factor) * self.dz;
+            if !self.julia {
+                self.dz.re = self.dz.re + FloatExp::from_f64(1.0);
+            }
+        }
+        if let Some(trap) = self.trap {
+            self.trap_distance = self.trap_distance.min(trap.distance(z));
+        }
+        if let Some(t) = self.average_term(z) {
+            self.avg_sum += t;
+            self.avg_last = t;
+            self.avg_count += 1;
+        }
+        self.z_prev = self.z;
+        self.z = z;
+    }
+
+    // The statistic of the new z for the averaging modes, all in [0, 1]
+    // None when it can't be computed for that z
+    fn average_term(&self, z: Complex<f64>) -> Option<f64> {
+        match self.mode {
+            // 1/2*sin(density*arg(z)) + 1/2
+            ColoringMode::StripeAverage => {
+                Some(0.5 * (STRIPE_DENSITY * z.im.atan2(z.re)).sin() + 0.5)
+            }
+            // Where |z| is between the bounds of the triangle inequality
+            // ||z^n| - |c|| <= |z^n + c| <= |z^n| + |c|
+            ColoringMode::TriangleAverage => {
+                let z_pow = self.z.norm_sqr_f64().sqrt().powi(self.power? as i32);
+                let c_abs = self.c.norm_sqr_f64().sqrt();
+                let low = (z_pow - c_abs).abs();
+                let high = z_pow + c_abs;
+                match high - low > 0.0 {
+                    true => Some((z.norm_sqr_f64().sqrt() - low) / (high - low)),
+                    false => None,
+                }
+            }
+            // How much the orbit turns, |arg((z - z_1) / (z_1 - z_2))| / pi
+            ColoringMode::CurvatureAverage => {
+                let turn = (z - self.z) / (self.z - self.z_prev);
+                let t = turn.im.atan2(turn.re).abs() / std::f64::consts::PI;
+                match t.is_finite() {
+                    true => Some(t),
+                    false => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // The average over the orbit and the same without its last term, blended
+    // with the fraction of the smooth iteration count so the bands disappear
+    pub fn average(&self, fraction: f64) -> f64 {
+        if self.avg_count < 2 {
+            return 0.0;
+        }
+        let avg = self.avg_sum / self.avg_count as f64;
+        let avg_prev = (self.avg_sum - self.avg_last) / (self.avg_count - 1) as f64;
+        (fraction * avg + (1.0 - fraction) * avg_prev).clamp(0.0, 1.0)
+    }
+
+    // |z|*ln|z| / |dz/dc|, in pixels. dz and the pixels are both past what f64
+    // holds when zoomed in deep enough, their product isn't
+    pub fn distance_estimate(&self, pixel_size: FloatExp) -> f64 {
+        let z_abs = self.z.norm_sqr_f64().sqrt();
+        let dz_pixels = self.dz.norm_sqr_floatexp().sqrt() * pixel_size;
+        z_abs * z_abs.ln() / dz_pixels.to_f64()
+    }
+}
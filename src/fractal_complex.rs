@@ -1,17 +1,21 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
 use rug::{
     Assign,
     float::Round,
-    ops::{AddFrom, DivAssignRound, MulAssignRound, MulFrom},
+    ops::{DivAssignRound, MulAssignRound, NegAssign, Pow},
 };
 use sfml::system::Vector2;
 
+use crate::fractal_engine::{EscapeNorm, FractalFormula};
+
 // I don't know why but this is faster than `core::f64::<impl f64>::abs`
 #[inline]
 fn f_abs(n: f64) -> f64 {
     f64::from_bits(0x7FFF_FFFF_FFFF_FFFF & n.to_bits())
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct Complex<T> {
     pub re: T,
     pub im: T,
@@ -23,6 +27,64 @@ impl<T> Complex<T> {
     }
 }
 
+impl<T: Add<Output = T>> Add for Complex<T> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl<T: Sub<Output = T>> Sub for Complex<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl<T: Neg<Output = T>> Neg for Complex<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.re, -self.im)
+    }
+}
+
+impl<T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Copy> Mul for Complex<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Div for Complex<f64> {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        let denom = rhs.norm_sqr_f64();
+        Self::new(
+            (self.re * rhs.re + self.im * rhs.im) / denom,
+            (self.im * rhs.re - self.re * rhs.im) / denom,
+        )
+    }
+}
+
+impl<T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Copy> Complex<T> {
+    // z^n, n is small enough for the multiplications to be fine
+    pub fn powu(self, n: u32) -> Self {
+        let mut r = self;
+        for _ in 1..n {
+            r = r * self;
+        }
+        r
+    }
+}
+
 impl Complex<f64> {
     pub fn map_pixel_value_f64(res: Self, center: Self, window: Self, coord: Self) -> Self {
         Self::new(
@@ -31,20 +93,302 @@ impl Complex<f64> {
         )
     }
 
-    pub fn f_sq_add_f64(&mut self, c: Self) {
-        (self.re, self.im) = (
-            self.re * self.re - self.im * self.im + c.re,
-            2.0 * self.re * self.im + c.im,
-        );
-    }
-
     pub fn abs_sum_f64(&self) -> f64 {
         f_abs(self.re) + f_abs(self.im)
     }
+
+    pub fn norm_sqr_f64(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn abs_max_f64(&self) -> f64 {
+        f64::max(f_abs(self.re), f_abs(self.im))
+    }
+
+    pub fn distance_f64(&self, norm: EscapeNorm) -> f64 {
+        match norm {
+            EscapeNorm::L1 => self.abs_sum_f64(),
+            EscapeNorm::Euclidean => self.norm_sqr_f64(),
+            EscapeNorm::LInf => self.abs_max_f64(),
+            EscapeNorm::Weighted(w_re, w_im) => w_re * self.re * self.re + w_im * self.im * self.im,
+        }
+    }
+
+    pub fn from_rug_f64(n: &rug::Complex) -> Self {
+        Self::new(n.real().to_f64(), n.imag().to_f64())
+    }
+}
+
+// Everything the formulas beyond z² + c are made of, the principal branch
+// every time there is more than one
+impl Complex<f64> {
+    pub fn conj_f64(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn abs_f64(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg_f64(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    fn from_polar_f64(abs: f64, arg: f64) -> Self {
+        Self::new(abs * arg.cos(), abs * arg.sin())
+    }
+
+    pub fn recip_f64(self) -> Self {
+        Self::new(1.0, 0.0) / self
+    }
+
+    // Square and multiply, 1/z^-n for the negative ones
+    pub fn powi_f64(self, n: i32) -> Self {
+        let mut result = Self::new(1.0, 0.0);
+        let mut base = self;
+        let mut exp = n.unsigned_abs();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            exp >>= 1;
+        }
+        match n < 0 {
+            true => result.recip_f64(),
+            false => result,
+        }
+    }
+
+    // ln(0) would give NaN, 0^w is what MPC gives : 1 for w = 0, 0 when Re(w)
+    // is positive, infinite when it's negative and undefined on the imaginary axis
+    fn zero_pow_f64(w: Self) -> Self {
+        match (w.re, w.im) {
+            (0.0, 0.0) => Self::new(1.0, 0.0),
+            (re, _) if re > 0.0 => Self::new(0.0, 0.0),
+            (re, _) if re < 0.0 => Self::new(f64::INFINITY, 0.0),
+            _ => Self::new(f64::NAN, f64::NAN),
+        }
+    }
+
+    pub fn powf_f64(self, x: f64) -> Self {
+        if self.re == 0.0 && self.im == 0.0 {
+            return Self::zero_pow_f64(Self::new(x, 0.0));
+        }
+        Self::from_polar_f64(self.abs_f64().powf(x), self.arg_f64() * x)
+    }
+
+    pub fn powc_f64(self, w: Self) -> Self {
+        if self.re == 0.0 && self.im == 0.0 {
+            return Self::zero_pow_f64(w);
+        }
+        (self.ln_f64() * w).exp_f64()
+    }
+
+    pub fn sqrt_f64(self) -> Self {
+        Self::from_polar_f64(self.abs_f64().sqrt(), self.arg_f64() / 2.0)
+    }
+
+    pub fn exp_f64(self) -> Self {
+        Self::from_polar_f64(self.re.exp(), self.im)
+    }
+
+    pub fn ln_f64(self) -> Self {
+        Self::new(self.abs_f64().ln(), self.arg_f64())
+    }
+
+    pub fn sin_f64(self) -> Self {
+        Self::new(
+            self.re.sin() * self.im.cosh(),
+            self.re.cos() * self.im.sinh(),
+        )
+    }
+
+    pub fn cos_f64(self) -> Self {
+        Self::new(
+            self.re.cos() * self.im.cosh(),
+            -self.re.sin() * self.im.sinh(),
+        )
+    }
+
+    pub fn tan_f64(self) -> Self {
+        self.sin_f64() / self.cos_f64()
+    }
+
+    pub fn sinh_f64(self) -> Self {
+        Self::new(
+            self.re.sinh() * self.im.cos(),
+            self.re.cosh() * self.im.sin(),
+        )
+    }
+
+    pub fn cosh_f64(self) -> Self {
+        Self::new(
+            self.re.cosh() * self.im.cos(),
+            self.re.sinh() * self.im.sin(),
+        )
+    }
+
+    pub fn tanh_f64(self) -> Self {
+        self.sinh_f64() / self.cosh_f64()
+    }
+}
+
+// Same as above with MPC doing the work, at the precision of the real part
+impl Complex<rug::Float> {
+    fn to_mpc(&self) -> rug::Complex {
+        rug::Complex::with_val(self.re.prec(), (&self.re, &self.im))
+    }
+
+    fn from_mpc(n: rug::Complex) -> Self {
+        let (re, im) = n.into_real_imag();
+        Self::new(re, im)
+    }
+
+    pub fn mul_rug(&self, rhs: &Self) -> Self {
+        Self::from_mpc(self.to_mpc() * rhs.to_mpc())
+    }
+
+    pub fn div_rug(&self, rhs: &Self) -> Self {
+        Self::from_mpc(self.to_mpc() / rhs.to_mpc())
+    }
+
+    pub fn conj_rug(&self) -> Self {
+        Self::new(self.re.clone(), -self.im.clone())
+    }
+
+    pub fn abs_rug(&self) -> rug::Float {
+        rug::Float::with_val(self.re.prec(), self.to_mpc().abs_ref())
+    }
+
+    pub fn arg_rug(&self) -> rug::Float {
+        rug::Float::with_val(self.re.prec(), self.to_mpc().arg_ref())
+    }
+
+    pub fn recip_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().recip())
+    }
+
+    pub fn powi_rug(&self, n: i32) -> Self {
+        Self::from_mpc(self.to_mpc().pow(n))
+    }
+
+    pub fn powf_rug(&self, x: &rug::Float) -> Self {
+        Self::from_mpc(self.to_mpc().pow(x))
+    }
+
+    pub fn powc_rug(&self, w: &Self) -> Self {
+        Self::from_mpc(self.to_mpc().pow(w.to_mpc()))
+    }
+
+    pub fn sqrt_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().sqrt())
+    }
+
+    pub fn exp_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().exp())
+    }
+
+    pub fn ln_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().ln())
+    }
+
+    pub fn sin_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().sin())
+    }
+
+    pub fn cos_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().cos())
+    }
+
+    pub fn tan_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().tan())
+    }
+
+    pub fn sinh_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().sinh())
+    }
+
+    pub fn cosh_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().cosh())
+    }
+
+    pub fn tanh_rug(&self) -> Self {
+        Self::from_mpc(self.to_mpc().tanh())
+    }
 }
 
-pub fn iter_gradient(iter: u32, seq_iter: u32) -> [u8; 4] {
-    let iter_norm = iter as f64 / seq_iter as f64;
+// Half the bits of f64, only for the shallow zooms
+impl Complex<f64> {
+    // Main cardioid and period 2 bulb, c is in the set without iterating
+    pub fn in_cardioid_or_bulb_f64(&self) -> bool {
+        let x = self.re - 0.25;
+        let y_sq = self.im * self.im;
+        let q = x * x + y_sq;
+        let cardioid = q * (q + x) <= 0.25 * y_sq;
+        let bulb = (self.re + 1.0) * (self.re + 1.0) + y_sq <= 0.0625;
+        cardioid || bulb
+    }
+}
+
+// Ulps of z under which the epsilon is only rounding noise
+pub const PERIODICITY_MIN_ULPS: f64 = 16.0;
+
+// Brent's cycle detection : z is saved every power of two iterations, if it
+// comes back to the saved value the orbit is periodic and will never escape
+pub struct Periodicity {
+    saved: Complex<f64>,
+    steps: u32,
+    limit: u32,
+    epsilon_sq: f64,
+}
+
+impl Periodicity {
+    pub fn new(epsilon: f64) -> Self {
+        Self {
+            saved: Complex::new(0.0, 0.0),
+            steps: 0,
+            limit: 2,
+            epsilon_sq: epsilon * epsilon,
+        }
+    }
+
+    // n is z rounded to f64, with the DoubleDouble and Rug backends the
+    // epsilon gets smaller than its ulps and two different orbits round to
+    // the same value, nothing is detected past that
+    pub fn check(&mut self, n: Complex<f64>) -> bool {
+        let resolution = PERIODICITY_MIN_ULPS * f64::EPSILON;
+        if self.epsilon_sq > n.norm_sqr_f64() * resolution * resolution
+            && (n - self.saved).norm_sqr_f64() <= self.epsilon_sq
+        {
+            return true;
+        }
+        self.steps += 1;
+        if self.steps == self.limit {
+            self.saved = n;
+            self.steps = 0;
+            self.limit *= 2;
+        }
+        false
+    }
+}
+
+// Normalized iteration count, continuous between the iteration steps
+// Squaring both distance and bailout doesn't change ln(distance)/ln(bailout)
+// so it works with every norm
+// The power is the one of the formula, z^n grows n times faster in log
+pub fn smooth_iter(iter: u32, distance: f64, bailout: f64, power: u32) -> f64 {
+    if bailout <= 1.0 {
+        return iter as f64;
+    }
+    f64::max(
+        iter as f64 + 1.0 - (distance.ln() / bailout.ln()).log(power as f64),
+        0.0,
+    )
+}
+
+pub fn iter_gradient(iter: f64, seq_iter: u32) -> [u8; 4] {
+    let iter_norm = iter / seq_iter as f64;
     let mid = 0.35;
     let (red, green, blue);
 
@@ -69,7 +413,8 @@ pub fn map_pixel_value_rug(
     window: &rug::Complex,
     coord: (i32, i32),
 ) -> rug::Complex {
-    let mut result = window.clone();
+    // At the precision of the center, it can be finer than the window
+    let mut result = rug::Complex::with_val(center.prec(), window);
     result
         .mut_real()
         .mul_assign_round(-(res.x as i32) + 2 * coord.0, Round::Nearest);
@@ -83,20 +428,285 @@ pub fn map_pixel_value_rug(
     result
         .mut_imag()
         .div_assign_round(2 * res.y, Round::Nearest);
-    result.add_from(center);
+    result += center;
     result
 }
 
-pub fn f_sq_add_rug(n: &rug::Complex, c: &rug::Complex) -> rug::Complex {
-    let mut r = rug::Complex::new(n.prec());
-    r.mut_real()
-        .assign(n.real() * n.real() - n.imag() * n.imag());
-    r.mut_imag().assign(n.real() * n.imag());
-    r.mut_imag().mul_from(2.0);
-    r.add_from(c);
-    r
+// What the escape time CPU backends iterate with : f32, f64, DoubleDouble,
+// Fixed and rug::Float. Every one of them goes through the same loop with
+// the methods of Complex<T> below
+pub trait FractalFloat: Clone {
+    // Temporaries of the iterations, only rug needs some to not allocate
+    type Scratch;
+
+    fn scratch(prec: u32) -> Self::Scratch;
+    // Keeps the precision of n for rug
+    fn from_rug(n: &rug::Float) -> Self;
+    fn to_f64(&self) -> f64;
+    // Without allocating for rug, the precision has to be the same
+    fn assign_from(&mut self, n: &Self);
+    // self = center + (fraction - 0.5) * window
+    fn map_fraction(&mut self, center: &Self, window: &Self, fraction: f64);
+    fn abs_in_place(&mut self);
+    fn neg_in_place(&mut self);
+    fn add_in_place(&mut self, n: &Self);
+    // z = z², each backend squares its own fast way
+    fn square(z: &mut Complex<Self>, scratch: &mut Self::Scratch);
+    // z = z^n, n > 2
+    fn powu(z: &mut Complex<Self>, n: u32, scratch: &mut Self::Scratch);
 }
 
-pub fn abs_sum_rug(n: &rug::Complex) -> f64 {
-    n.real().to_f64().abs() + n.imag().to_f64().abs()
+impl<T: FractalFloat> Complex<T> {
+    pub fn from_rug(n: &rug::Complex) -> Self {
+        Self::new(T::from_rug(n.real()), T::from_rug(n.imag()))
+    }
+
+    pub fn to_f64(&self) -> Complex<f64> {
+        Complex::new(self.re.to_f64(), self.im.to_f64())
+    }
+
+    pub fn assign_from(&mut self, n: &Self) {
+        self.re.assign_from(&n.re);
+        self.im.assign_from(&n.im);
+    }
+
+    // Same as `map_pixel_value_f64`, written over self
+    pub fn map_pixel_value(
+        &mut self,
+        res: Complex<f64>,
+        center: &Self,
+        window: &Self,
+        coord: Complex<f64>,
+    ) {
+        self.re
+            .map_fraction(&center.re, &window.re, coord.re / res.re);
+        self.im
+            .map_fraction(&center.im, &window.im, (res.im - coord.im) / res.im);
+    }
+
+    // z = f(z) + c, the same for every backend
+    pub fn iterate(&mut self, c: &Self, formula: FractalFormula, scratch: &mut T::Scratch) {
+        match formula {
+            FractalFormula::Multibrot(2) => T::square(self, scratch),
+            FractalFormula::Multibrot(n) => T::powu(self, n, scratch),
+            FractalFormula::BurningShip | FractalFormula::Tricorn | FractalFormula::Celtic => {
+                T::square(self, scratch);
+                self.fold_square(formula);
+            }
+        }
+        self.re.add_in_place(&c.re);
+        self.im.add_in_place(&c.im);
+    }
+
+    // The Burning Ship, the Tricorn and the Celtic are z² with a sign changed,
+    // self is (re² - im²) + i*(2*re*im) when this is called
+    pub fn fold_square(&mut self, formula: FractalFormula) {
+        match formula {
+            FractalFormula::BurningShip => self.im.abs_in_place(),
+            FractalFormula::Tricorn => self.im.neg_in_place(),
+            FractalFormula::Celtic => self.re.abs_in_place(),
+            FractalFormula::Multibrot(_) => {}
+        }
+    }
+}
+
+impl FractalFloat for f64 {
+    type Scratch = ();
+
+    fn scratch(_prec: u32) {}
+
+    fn from_rug(n: &rug::Float) -> Self {
+        n.to_f64()
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+
+    fn assign_from(&mut self, n: &Self) {
+        *self = *n;
+    }
+
+    fn map_fraction(&mut self, center: &Self, window: &Self, fraction: f64) {
+        *self = center + (fraction - 0.5) * window;
+    }
+
+    fn abs_in_place(&mut self) {
+        *self = f_abs(*self);
+    }
+
+    fn neg_in_place(&mut self) {
+        *self = -*self;
+    }
+
+    fn square(z: &mut Complex<Self>, _: &mut ()) {
+        (z.re, z.im) = (z.re * z.re - z.im * z.im, 2.0 * z.re * z.im);
+    }
+
+    fn powu(z: &mut Complex<Self>, n: u32, _: &mut ()) {
+        *z = z.powu(n);
+    }
+
+    fn add_in_place(&mut self, n: &Self) {
+        *self += *n;
+    }
+}
+
+impl FractalFloat for f32 {
+    type Scratch = ();
+
+    fn scratch(_prec: u32) {}
+
+    fn from_rug(n: &rug::Float) -> Self {
+        n.to_f32()
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+
+    fn assign_from(&mut self, n: &Self) {
+        *self = *n;
+    }
+
+    // In f64, the window can be smaller than what f32 can add to the center
+    fn map_fraction(&mut self, center: &Self, window: &Self, fraction: f64) {
+        *self = (*center as f64 + (fraction - 0.5) * *window as f64) as f32;
+    }
+
+    fn abs_in_place(&mut self) {
+        *self = self.abs();
+    }
+
+    fn neg_in_place(&mut self) {
+        *self = -*self;
+    }
+
+    fn square(z: &mut Complex<Self>, _: &mut ()) {
+        (z.re, z.im) = (z.re * z.re - z.im * z.im, 2.0 * z.re * z.im);
+    }
+
+    fn powu(z: &mut Complex<Self>, n: u32, _: &mut ()) {
+        *z = z.powu(n);
+    }
+
+    fn add_in_place(&mut self, n: &Self) {
+        *self += *n;
+    }
+}
+
+// Everything the Rug iterations write to besides z, kept from one pixel to
+// the next. MPFR only allocates when a float is created or gets a higher
+// precision, so iterating with these doesn't allocate at all
+pub struct RugScratch {
+    re_sq: rug::Float,
+    im_sq: rug::Float,
+    base: rug::Complex, // z for the Multibrots, z^n is z*z*...
+    pow: rug::Complex,
+    // pow * base lands here then gets swapped with pow, *= would allocate
+    pow_next: rug::Complex,
+}
+
+impl FractalFloat for rug::Float {
+    type Scratch = RugScratch;
+
+    fn scratch(prec: u32) -> RugScratch {
+        RugScratch {
+            re_sq: rug::Float::new(prec),
+            im_sq: rug::Float::new(prec),
+            base: rug::Complex::new(prec),
+            pow: rug::Complex::new(prec),
+            pow_next: rug::Complex::new(prec),
+        }
+    }
+
+    fn from_rug(n: &rug::Float) -> Self {
+        n.clone()
+    }
+
+    fn to_f64(&self) -> f64 {
+        rug::Float::to_f64(self)
+    }
+
+    fn assign_from(&mut self, n: &Self) {
+        self.assign(n);
+    }
+
+    fn map_fraction(&mut self, center: &Self, window: &Self, fraction: f64) {
+        self.assign(window * (fraction - 0.5));
+        *self += center;
+    }
+
+    fn abs_in_place(&mut self) {
+        self.abs_mut();
+    }
+
+    fn neg_in_place(&mut self) {
+        self.neg_assign();
+    }
+
+    fn add_in_place(&mut self, n: &Self) {
+        *self += n;
+    }
+
+    // (re² - im²) + i*(2*re*im), in place
+    fn square(z: &mut Complex<Self>, scratch: &mut RugScratch) {
+        scratch.re_sq.assign(z.re.square_ref());
+        scratch.im_sq.assign(z.im.square_ref());
+        z.im *= &z.re;
+        z.im <<= 1;
+        z.re.assign(&scratch.re_sq - &scratch.im_sq);
+    }
+
+    fn powu(z: &mut Complex<Self>, n: u32, scratch: &mut RugScratch) {
+        scratch.base.assign((&z.re, &z.im));
+        scratch.pow.assign(&scratch.base);
+        for _ in 1..n {
+            scratch.pow_next.assign(&scratch.pow * &scratch.base);
+            std::mem::swap(&mut scratch.pow, &mut scratch.pow_next);
+        }
+        z.re.assign(scratch.pow.real());
+        z.im.assign(scratch.pow.imag());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fractal_double_double::DoubleDouble, fractal_fixed::Fixed};
+
+    // c is in the cardioid of every formula, the orbits stay small
+    const STEPS: usize = 20;
+
+    fn orbit<T: FractalFloat>(formula: FractalFormula, prec: u32) -> Complex<f64> {
+        let to_t =
+            |re: f64, im: f64| Complex::<T>::from_rug(&rug::Complex::with_val(prec, (re, im)));
+        let mut z = to_t(0.3, -0.4);
+        let c = to_t(-0.2, 0.1);
+        let mut scratch = T::scratch(prec);
+        for _ in 0..STEPS {
+            z.iterate(&c, formula, &mut scratch);
+        }
+        z.to_f64()
+    }
+
+    #[test]
+    fn backends_agree() {
+        for (name, formula) in [
+            ("z²", FractalFormula::MANDELBROT),
+            ("z³", FractalFormula::Multibrot(3)),
+            ("Burning Ship", FractalFormula::BurningShip),
+            ("Tricorn", FractalFormula::Tricorn),
+            ("Celtic", FractalFormula::Celtic),
+        ] {
+            let expected = orbit::<rug::Float>(formula, 128);
+            let close = |z: Complex<f64>, tolerance: f64| {
+                assert!((z - expected).norm_sqr_f64().sqrt() <= tolerance, "{name}");
+            };
+            close(orbit::<f64>(formula, 128), 1e-13);
+            close(orbit::<DoubleDouble>(formula, 128), 1e-13);
+            close(orbit::<Fixed<2>>(formula, 128), 1e-13);
+            close(orbit::<f32>(formula, 128), 1e-4);
+        }
+    }
 }
@@ -1,18 +1,42 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use rug;
+use rug::{self, Assign};
 use sfml::{graphics::glsl::Vec4, system::Vector2u};
 
-use crate::fractal_complex::Complex;
+use crate::{
+    fractal_complex::Complex,
+    fractal_expr::{self, Expr, StartZ},
+    fractal_finder::Misiurewicz,
+    fractal_fixed::{self, FIXED_INT_BITS, FIXED_MAX_LIMBS},
+    fractal_floatexp::FloatExp,
+};
 
 // Some Constants
 pub const FRCTL_CTX_CMPLX_PREC: u32 = 128;
+pub const MIN_RUG_PREC: u32 = 64;
+// Bits on top of the ones the pixels need, the iterations lose some
+pub const BASE_PREC_MARGIN: u32 = 16;
 pub const INIT_SEQ_ITER: u32 = 75;
 pub const SEQ_ITER_FACT_BASE: f64 = 50.;
 pub const BASE_CENTER: f64 = -0.72;
 pub const BASE_WINDOW: (f64, f64) = (3.3, 0.0);
+pub const BASE_JULIA_CENTER: f64 = 0.0;
+pub const BASE_JULIA_C: (f64, f64) = (-0.8, 0.156);
+// The usual Nebulabrot, red gets the long orbits and blue the short ones
+pub const BASE_BUDDHA_ITERS: [u32; 3] = [5000, 500, 50];
+pub const BASE_BUDDHA_SAMPLES: u32 = 200_000;
+pub const BASE_BUDDHA_MAX_PASSES: u32 = 100;
+pub const BASE_LYAPUNOV_CENTER: (f64, f64) = (3.0, 3.0);
+pub const BASE_LYAPUNOV_SEQ: &str = "AB";
+pub const BASE_CUSTOM_FORMULA: &str = "z^2 + c";
+pub const BASE_CUSTOM_BAILOUT: f64 = 10.;
+pub const BASE_TRAP_RADIUS: f64 = 0.5;
 pub const BASE_WORKER_COUNT: usize = 1;
 pub const BASE_CONV_DIST: f64 = 2.;
+// Fraction of a pixel under which a periodic orbit is considered back to its start
+pub const PERIODICITY_EPS_FACT: f64 = 1e-3;
+// Pixel size relative to the center under which f32 is too coarse, about 2^-18
+pub const F32_MIN_PIXEL_SIZE_FACT: f64 = 4e-6;
 
 pub mod lodiv {
     pub const HIGHEST: u32 = 1;
@@ -31,20 +55,189 @@ pub struct FractalContext {
     pub seq_iter: u32,
     pub auto_seq_iter: bool,
     pub auto_seq_iter_fact: f64,
+    pub auto_prec: bool,
+    pub prec_margin: u32,
+    pub fixed_point: bool, // instead of MPFR on the Rug backend when it fits
     pub reload_durs: Vec<Duration>,
     pub engine_enabled: bool,
     pub worker_count: usize,
     pub converge_distance: f64,
     pub has_resized: bool,
+    pub series_approx: bool,
+    pub skipped_iters: u32,
+    pub smooth_coloring: bool,
+    pub escape_norm: EscapeNorm,
+    pub interior_check: bool,
+    pub short_circuited: Vec<u32>,
+    pub coloring_mode: ColoringMode,
+    pub julia: bool,
+    pub julia_c: rug::Complex,
+    pub formula: FractalFormula,
+    pub mode: FractalMode,
+    pub newton_coeffs: Vec<Complex<f64>>, // coeffs[k] goes with z^k
+    pub buddha_iters: [u32; 3],           // max iterations of the R, G and B channels
+    pub buddha_samples: u32,              // per worker per pass
+    pub buddha_max_passes: u32,
+    pub buddha_passes: u32,
+    pub lyapunov_seq: String,
+    pub custom_formula: String, // what was typed, it may not parse
+    pub custom_expr: Arc<Expr>, // the last custom formula that parsed
+    pub custom_start: StartZ,
+    pub custom_bailout: f64, // the converge distance of the custom formulas
+    pub orbit_trap: OrbitTrap,
+    pub minibrot_period: Option<u32>, // of the last minibrot found
+    pub misiurewicz: Option<Misiurewicz>, // the last one found
 }
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum FractalBackend {
+    // Falls back to F64 when the pixels get too small for it
+    F32,
     F64,
+    DoubleDouble,
     Rug,
+    Perturbation,
     Shader,
 }
 
+// What gets drawn
+#[derive(Copy, Clone, PartialEq)]
+pub enum FractalMode {
+    // Escape time, with the formula of the context
+    Escape,
+    // Newton's method on the polynomial of the context
+    Newton,
+    // Density of the escaping orbits, the Nebulabrot when the channels have
+    // different iteration limits
+    Buddhabrot,
+    // Lyapunov exponent of the logistic map, the pixel being (a, b)
+    Lyapunov,
+    // Escape time of a formula typed by the user
+    Custom,
+}
+
+// How far z is from 0 when checking if it escaped
+#[derive(Copy, Clone, PartialEq, Default)]
+pub enum EscapeNorm {
+    // |re| + |im|
+    L1,
+    // re² + im², the one everyone uses
+    #[default]
+    Euclidean,
+    // max(|re|, |im|)
+    LInf,
+    // w_re*re² + w_im*im²
+    Weighted(f64, f64),
+}
+
+// How the pixels that escaped are colored
+#[derive(Copy, Clone, PartialEq, Default)]
+pub enum ColoringMode {
+    // Gradient on the (smooth) iteration count
+    #[default]
+    Iteration,
+    // Thin lines where the distance estimate is under a pixel
+    DistanceBoundary,
+    // Iteration gradient, darker close to the boundary
+    DistanceDarkened,
+    // Gradient on how close the orbit got to the trap, inside the set too
+    OrbitTrap,
+    // Gradient on a statistic averaged over the orbit : the angle of z, where
+    // |z| is in the triangle inequality, how much the orbit turns
+    StripeAverage,
+    TriangleAverage,
+    CurvatureAverage,
+}
+
+impl ColoringMode {
+    // The distance estimate differentiates z^n + c and the triangle inequality
+    // bounds it, neither works with the custom formulas
+    pub fn needs_power(self) -> bool {
+        matches!(
+            self,
+            Self::DistanceBoundary | Self::DistanceDarkened | Self::TriangleAverage
+        )
+    }
+}
+
+// What the orbits get measured against with `ColoringMode::OrbitTrap`
+#[derive(Copy, Clone, PartialEq)]
+pub enum TrapShape {
+    Point,
+    // Goes through the center
+    Line,
+    // Two perpendicular lines crossing at the center
+    Cross,
+    Circle,
+}
+
+#[derive(Copy, Clone)]
+pub struct OrbitTrap {
+    pub shape: TrapShape,
+    pub center: Complex<f64>,
+    pub radius: f64, // Circle only
+    pub angle: f64,  // Line and Cross only, in radians
+}
+
+impl Default for OrbitTrap {
+    fn default() -> Self {
+        Self {
+            shape: TrapShape::Point,
+            center: Complex::new(0.0, 0.0),
+            radius: BASE_TRAP_RADIUS,
+            angle: 0.0,
+        }
+    }
+}
+
+impl OrbitTrap {
+    // Same as trap_distance in fragment.glsl
+    pub fn distance(&self, z: Complex<f64>) -> f64 {
+        let d = z - self.center;
+        let (sin, cos) = self.angle.sin_cos();
+        // Distance to the line along the angle, and to the one perpendicular to it
+        let along = (d.im * cos - d.re * sin).abs();
+        let across = (d.re * cos + d.im * sin).abs();
+        match self.shape {
+            TrapShape::Point => d.norm_sqr_f64().sqrt(),
+            TrapShape::Line => along,
+            TrapShape::Cross => along.min(across),
+            TrapShape::Circle => (d.norm_sqr_f64().sqrt() - self.radius).abs(),
+        }
+    }
+}
+
+// What z gets iterated with
+#[derive(Copy, Clone, PartialEq)]
+pub enum FractalFormula {
+    // z^n + c, n = 2 is the Mandelbrot set
+    Multibrot(u32),
+    // (|re| + i|im|)² + c
+    BurningShip,
+    // conj(z)² + c, aka the Mandelbar
+    Tricorn,
+    // |re(z²)| + i*im(z²) + c
+    Celtic,
+}
+
+impl Default for FractalFormula {
+    fn default() -> Self {
+        Self::MANDELBROT
+    }
+}
+
+impl FractalFormula {
+    pub const MANDELBROT: Self = Self::Multibrot(2);
+
+    // Only the Multibrots have a power other than 2
+    pub fn power(self) -> u32 {
+        match self {
+            Self::Multibrot(n) => n,
+            Self::BurningShip | Self::Tricorn | Self::Celtic => 2,
+        }
+    }
+}
+
 pub enum FractalNotif {
     Commence,
     Shutdown,
@@ -62,15 +255,229 @@ impl Default for FractalContext {
             seq_iter: INIT_SEQ_ITER,
             auto_seq_iter: true,
             auto_seq_iter_fact: SEQ_ITER_FACT_BASE,
+            auto_prec: true,
+            prec_margin: BASE_PREC_MARGIN,
+            fixed_point: true,
             reload_durs: vec![Duration::ZERO],
             engine_enabled: true,
             worker_count: BASE_WORKER_COUNT,
             converge_distance: BASE_CONV_DIST,
             has_resized: true,
+            series_approx: true,
+            skipped_iters: 0,
+            smooth_coloring: true,
+            escape_norm: EscapeNorm::Euclidean,
+            interior_check: true,
+            short_circuited: vec![0],
+            coloring_mode: ColoringMode::Iteration,
+            julia: false,
+            julia_c: rug::Complex::with_val(FRCTL_CTX_CMPLX_PREC, BASE_JULIA_C),
+            formula: FractalFormula::MANDELBROT,
+            mode: FractalMode::Escape,
+            // z³ - 1
+            newton_coeffs: vec![
+                Complex::new(-1.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(1.0, 0.0),
+            ],
+            buddha_iters: BASE_BUDDHA_ITERS,
+            buddha_samples: BASE_BUDDHA_SAMPLES,
+            buddha_max_passes: BASE_BUDDHA_MAX_PASSES,
+            buddha_passes: 0,
+            lyapunov_seq: BASE_LYAPUNOV_SEQ.to_string(),
+            custom_formula: BASE_CUSTOM_FORMULA.to_string(),
+            custom_expr: Arc::new(fractal_expr::parse(BASE_CUSTOM_FORMULA).unwrap()),
+            custom_start: StartZ::Zero,
+            custom_bailout: BASE_CUSTOM_BAILOUT,
+            orbit_trap: OrbitTrap::default(),
+            minibrot_period: None,
+            misiurewicz: None,
         }
     }
 }
 
+impl FractalContext {
+    // exp(z) & co need a lot more room than z² + c
+    fn mode_converge_distance(&self) -> f64 {
+        match self.mode {
+            FractalMode::Custom => self.custom_bailout,
+            _ => self.converge_distance,
+        }
+    }
+
+    // What the escape distance is compared to, the squared norms are
+    // compared to the squared converge distance
+    pub fn bailout(&self) -> f64 {
+        let converge_distance = self.mode_converge_distance();
+        match self.escape_norm {
+            EscapeNorm::Euclidean | EscapeNorm::Weighted(_, _) => {
+                converge_distance * converge_distance
+            }
+            EscapeNorm::L1 | EscapeNorm::LInf => converge_distance,
+        }
+    }
+
+    // |z| under which z hasn't escaped whatever the norm
+    pub fn escape_radius(&self) -> f64 {
+        let converge_distance = self.mode_converge_distance();
+        match self.escape_norm {
+            EscapeNorm::Euclidean | EscapeNorm::LInf => converge_distance,
+            EscapeNorm::L1 => converge_distance / std::f64::consts::SQRT_2,
+            EscapeNorm::Weighted(w_re, w_im) => converge_distance / w_re.max(w_im).sqrt(),
+        }
+    }
+
+    pub fn reset_window(&mut self) {
+        self.window = rug::Complex::with_val(FRCTL_CTX_CMPLX_PREC, BASE_WINDOW);
+        let mut new_real = self.window.real().clone();
+        new_real *= self.res.y as f32 / self.res.x as f32;
+        self.window.mut_imag().assign(new_real);
+        if self.auto_seq_iter {
+            self.seq_iter = seq_iters_formula(&self.window, self.auto_seq_iter_fact);
+        }
+        self.adjust_auto_prec();
+    }
+
+    // Mantissa bits needed for every pixel of the view to be a different
+    // number : as many as there are between the size of the center and the
+    // size of a pixel
+    pub fn required_prec(&self) -> u32 {
+        // 0 has no exponent, it doesn't need any bit
+        let exp = |n: &rug::Float| n.get_exp().unwrap_or(i32::MIN);
+        let center_exp = exp(self.center.real()).max(exp(self.center.imag())).max(1);
+        let res_log2 = (self.res.x / self.lodiv).max(1).ilog2() as i32 + 1;
+        let pixel_exp = exp(self.window.real()).saturating_sub(res_log2);
+        (center_exp.saturating_sub(pixel_exp).max(0) as u32).max(MIN_RUG_PREC)
+    }
+
+    pub fn set_prec(&mut self, prec: u32) {
+        self.window.set_prec(prec);
+        self.center.set_prec(prec);
+        self.julia_c.set_prec(prec);
+    }
+
+    // Follows the zoom when the precision is automatic, called every time the
+    // window, the center or the resolution changes. Only the working precision
+    // goes down when zooming out, the center and julia_c keep their digits or
+    // the deep coordinates would be truncated
+    pub fn adjust_auto_prec(&mut self) {
+        if self.auto_prec {
+            let prec = self.required_prec() + self.prec_margin;
+            self.window.set_prec(prec);
+            if self.center.prec().0 < prec {
+                self.center.set_prec(prec);
+            }
+            if self.julia_c.prec().0 < prec {
+                self.julia_c.set_prec(prec);
+            }
+        }
+    }
+
+    // The Julia sets, the Newton fractals and the custom formulas are centered
+    // on 0, the Mandelbrot set isn't, the Lyapunov fractals are where a and b are interesting
+    pub fn base_center(&self) -> rug::Complex {
+        match (self.mode, self.julia) {
+            (FractalMode::Lyapunov, _) => {
+                rug::Complex::with_val(FRCTL_CTX_CMPLX_PREC, BASE_LYAPUNOV_CENTER)
+            }
+            (FractalMode::Newton | FractalMode::Custom, _) | (_, true) => {
+                rug::Complex::with_val(FRCTL_CTX_CMPLX_PREC, BASE_JULIA_CENTER)
+            }
+            _ => rug::Complex::with_val(FRCTL_CTX_CMPLX_PREC, BASE_CENTER),
+        }
+    }
+
+    // Not every mode runs on every backend, the closest one that does is used
+    pub fn supported_backend(&self, backend: FractalBackend) -> FractalBackend {
+        match (self.mode, backend) {
+            (FractalMode::Escape, _) => backend,
+            (
+                FractalMode::Newton | FractalMode::Custom,
+                FractalBackend::F32
+                | FractalBackend::F64
+                | FractalBackend::DoubleDouble
+                | FractalBackend::Shader,
+            ) => FractalBackend::F64,
+            (
+                FractalMode::Newton | FractalMode::Custom,
+                FractalBackend::Rug | FractalBackend::Perturbation,
+            ) => FractalBackend::Rug,
+            // The orbits are only drawn in f64, and a and b don't need more
+            (FractalMode::Buddhabrot | FractalMode::Lyapunov, _) => FractalBackend::F64,
+        }
+    }
+
+    // Skipped interior pixels are black, the orbit traps need the whole orbit
+    // to color them
+    pub fn skips_interior(&self) -> bool {
+        self.interior_check
+            && (self.mode != FractalMode::Escape || self.coloring_mode != ColoringMode::OrbitTrap)
+    }
+
+    // What the escaping orbits are smoothed and differentiated with, None for
+    // the custom formulas which aren't z^n + c
+    pub fn power(&self) -> Option<u32> {
+        match self.mode {
+            FractalMode::Custom => None,
+            _ => Some(self.formula.power()),
+        }
+    }
+
+    // The cardioid and the bulb are only where they are for the Mandelbrot set,
+    // the custom formulas have to check the mode themselves
+    pub fn cardioid_check(&self) -> bool {
+        self.skips_interior() && !self.julia && self.formula == FractalFormula::MANDELBROT
+    }
+
+    pub fn pixel_size(&self) -> f64 {
+        self.window.real().to_f64() / (self.res.x / self.lodiv) as f64
+    }
+
+    // The f64 one is 0 past 1e-308
+    pub fn pixel_size_floatexp(&self) -> FloatExp {
+        FloatExp::from_rug(self.window.real())
+            * FloatExp::from_f64(1.0 / (self.res.x / self.lodiv) as f64)
+    }
+
+    // Limbs of the fixed-point numbers for the precision of the window, None
+    // when MPFR has to be used
+    pub fn fixed_point_limbs(&self) -> Option<usize> {
+        // z starts at the pixel, c is the pixel too for the Mandelbrot set
+        let center = Complex::from_rug_f64(&self.center);
+        let window = Complex::from_rug_f64(&self.window);
+        let pixel_abs = center.norm_sqr_f64().sqrt() + window.norm_sqr_f64().sqrt();
+        let c_abs = match self.julia {
+            true => Complex::from_rug_f64(&self.julia_c).norm_sqr_f64().sqrt(),
+            false => pixel_abs,
+        };
+        let fits = fractal_fixed::fixed_point_fits(
+            self.escape_norm,
+            self.converge_distance,
+            self.formula.power(),
+            pixel_abs,
+            c_abs,
+        );
+        let limbs = (self.window.prec().0 + FIXED_INT_BITS).div_ceil(64) as usize;
+        (self.fixed_point && fits && limbs <= FIXED_MAX_LIMBS).then_some(limbs.max(2))
+    }
+
+    // f32 has 24 bits, a few of them are kept for the error of the iterations
+    pub fn f32_is_enough(&self) -> bool {
+        let magnitude = self
+            .center
+            .real()
+            .to_f64()
+            .abs()
+            .max(self.center.imag().to_f64().abs());
+        self.pixel_size() > magnitude.max(1.0) * F32_MIN_PIXEL_SIZE_FACT
+    }
+
+    pub fn periodicity_epsilon(&self) -> f64 {
+        self.pixel_size() * PERIODICITY_EPS_FACT
+    }
+}
+
 pub trait FractalEngine {
     fn commence(&self);
 
@@ -94,12 +501,54 @@ pub trait FractalEngine {
 
     fn set_auto_seq_iter_fact(&mut self, auto_seq_iter_fact: f64);
 
+    fn set_auto_prec(&mut self, auto_prec: bool);
+
+    fn set_prec_margin(&mut self, prec_margin: u32);
+
+    fn set_fixed_point(&mut self, fixed_point: bool);
+
     fn set_workers(&mut self, workers: usize);
 
     fn set_backend(&mut self, backend: FractalBackend);
 
     fn set_converge_distance(&mut self, converge_distance: f64);
 
+    fn set_series_approx(&mut self, series_approx: bool);
+
+    fn set_smooth_coloring(&mut self, smooth_coloring: bool);
+
+    fn set_escape_norm(&mut self, escape_norm: EscapeNorm);
+
+    fn set_interior_check(&mut self, interior_check: bool);
+
+    fn set_coloring_mode(&mut self, coloring_mode: ColoringMode);
+
+    fn set_julia(&mut self, julia: bool);
+
+    fn set_julia_c(&mut self, julia_c: Complex<f64>);
+
+    fn set_formula(&mut self, formula: FractalFormula);
+
+    fn set_mode(&mut self, mode: FractalMode);
+
+    fn set_newton_coeffs(&mut self, newton_coeffs: Vec<Complex<f64>>);
+
+    fn set_buddha_iters(&mut self, buddha_iters: [u32; 3]);
+
+    fn set_buddha_samples(&mut self, buddha_samples: u32);
+
+    fn set_buddha_max_passes(&mut self, buddha_max_passes: u32);
+
+    fn set_lyapunov_seq(&mut self, lyapunov_seq: String);
+
+    fn set_custom_formula(&mut self, custom_formula: String);
+
+    fn set_custom_start(&mut self, custom_start: StartZ);
+
+    fn set_custom_bailout(&mut self, custom_bailout: f64);
+
+    fn set_orbit_trap(&mut self, orbit_trap: OrbitTrap);
+
     fn gui_bottom_panel(&mut self, ui: &mut egui::Ui);
 
     fn gui_central_panel(&mut self, ui: &mut egui::Ui);
@@ -114,6 +563,11 @@ pub fn two_f64_to_vec4(a: f64, b: f64) -> Vec4 {
 }
 
 pub fn seq_iters_formula(window: &rug::Complex, factor: f64) -> u32 {
-    let window_size = f64::max(window.real().to_f64(), window.imag().to_f64());
-    INIT_SEQ_ITER + (factor * -window_size.log2()) as u32
+    // log2 of the window without going through an f64 that would underflow
+    let log2 = |n: &rug::Float| {
+        let (mant, exp) = n.to_f64_exp();
+        mant.log2() + exp as f64
+    };
+    let window_size_log2 = f64::max(log2(window.real()), log2(window.imag()));
+    INIT_SEQ_ITER + (factor * -window_size_log2) as u32
 }
@@ -0,0 +1,620 @@
+use std::fmt;
+
+use crate::fractal_complex::Complex;
+
+// The parser and the evaluation recurse, a pasted wall of '(' or '-' would
+// overflow the stack. Every level goes through Parser::unary
+const MAX_DEPTH: usize = 64;
+// The tree is as deep as the longest chain of operators, "z + z + z..." too
+const MAX_TOKENS: usize = 512;
+
+// What the typed formulas are made of, z and c are the ones of the iteration
+pub enum Expr {
+    Z,
+    C,
+    Number(Complex<f64>), // the real numbers and i
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+    Call(Function, Box<Expr>),
+}
+
+#[derive(Copy, Clone)]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Exp,
+    Ln,
+    Sqrt,
+    Recip,
+    Conj,
+    Abs,
+    Arg,
+}
+
+impl Function {
+    const NAMES: [(&str, Self); 14] = [
+        ("sin", Self::Sin),
+        ("cos", Self::Cos),
+        ("tan", Self::Tan),
+        ("sinh", Self::Sinh),
+        ("cosh", Self::Cosh),
+        ("tanh", Self::Tanh),
+        ("exp", Self::Exp),
+        ("ln", Self::Ln),
+        ("log", Self::Ln),
+        ("sqrt", Self::Sqrt),
+        ("recip", Self::Recip),
+        ("conj", Self::Conj),
+        ("abs", Self::Abs),
+        ("arg", Self::Arg),
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(function_name, _)| *function_name == name)
+            .map(|&(_, function)| function)
+    }
+}
+
+// Where z starts for the pixel c, the Julia sets always start at the pixel
+#[derive(Copy, Clone, PartialEq, Default)]
+pub enum StartZ {
+    #[default]
+    Zero,
+    Pixel,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    pub column: usize, // of the character that went wrong, from 1
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Column {} : {}", self.column, self.message)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    LParen,
+    RParen,
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "'{n}'"),
+            Self::Ident(name) => write!(f, "'{name}'"),
+            Self::Plus => write!(f, "'+'"),
+            Self::Minus => write!(f, "'-'"),
+            Self::Star => write!(f, "'*'"),
+            Self::Slash => write!(f, "'/'"),
+            Self::Caret => write!(f, "'^'"),
+            Self::LParen => write!(f, "'('"),
+            Self::RParen => write!(f, "')'"),
+            Self::End => write!(f, "the end of the formula"),
+        }
+    }
+}
+
+// Every token with the index of its first character
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let token = match chars[i] {
+            ' ' | '\t' => {
+                i += 1;
+                continue;
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '^' => Token::Caret,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ch if ch.is_ascii_digit() || ch == '.' => {
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let number = text.parse().map_err(|_| ParseError {
+                    column: start + 1,
+                    message: format!("'{text}' is not a number"),
+                })?;
+                tokens.push((start, Token::Number(number)));
+                continue;
+            }
+            ch if ch.is_alphabetic() => {
+                while i < chars.len() && chars[i].is_alphanumeric() {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect();
+                tokens.push((start, Token::Ident(name.to_lowercase())));
+                continue;
+            }
+            ch => {
+                return Err(ParseError {
+                    column: start + 1,
+                    message: format!("Unexpected character '{ch}'"),
+                });
+            }
+        };
+        tokens.push((start, token));
+        i += 1;
+    }
+    if tokens.len() > MAX_TOKENS {
+        return Err(ParseError {
+            column: tokens[MAX_TOKENS].0 + 1,
+            message: format!(
+                "The formula is too long, {MAX_TOKENS} numbers, names and symbols at most"
+            ),
+        });
+    }
+    tokens.push((chars.len(), Token::End));
+    Ok(tokens)
+}
+
+// Recursive descent, from the loosest to the tightest :
+// sum := product (('+' | '-') product)*
+// product := unary (('*' | '/')? unary)*, "2z" is 2*z
+// unary := '-' unary | power
+// power := atom ('^' unary)?, right associative
+// atom := number | z | c | i | function '(' sum ')' | '(' sum ')'
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    depth: usize, // of Parser::unary calls
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].1.clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            column: self.tokens[self.pos].0 + 1,
+            message,
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        match *self.peek() == token {
+            true => {
+                self.next();
+                Ok(())
+            }
+            false => Err(self.error(format!("Expected {token}, found {}", self.peek()))),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.product()?;
+        loop {
+            lhs = match self.peek() {
+                Token::Plus => {
+                    self.next();
+                    Expr::Add(Box::new(lhs), Box::new(self.product()?))
+                }
+                Token::Minus => {
+                    self.next();
+                    Expr::Sub(Box::new(lhs), Box::new(self.product()?))
+                }
+                _ => return Ok(lhs),
+            };
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        loop {
+            lhs = match self.peek() {
+                Token::Star => {
+                    self.next();
+                    Expr::Mul(Box::new(lhs), Box::new(self.unary()?))
+                }
+                Token::Slash => {
+                    self.next();
+                    Expr::Div(Box::new(lhs), Box::new(self.unary()?))
+                }
+                Token::Number(_) | Token::Ident(_) | Token::LParen => {
+                    Expr::Mul(Box::new(lhs), Box::new(self.unary()?))
+                }
+                _ => return Ok(lhs),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(format!(
+                "Nested too deep, {MAX_DEPTH} levels of parentheses, functions, powers and '-' at most"
+            )));
+        }
+        self.depth += 1;
+        let expr = self.unary_inner();
+        self.depth -= 1;
+        expr
+    }
+
+    fn unary_inner(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Token::Minus => {
+                self.next();
+                // Folded so that z^-2 still takes the integer power
+                match self.unary()? {
+                    Expr::Number(n) => Ok(Expr::Number(-n)),
+                    expr => Ok(Expr::Neg(Box::new(expr))),
+                }
+            }
+            _ => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.atom()?;
+        match self.peek() {
+            Token::Caret => {
+                self.next();
+                Ok(Expr::Pow(Box::new(base), Box::new(self.unary()?)))
+            }
+            _ => Ok(base),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        let error = self.error(format!(
+            "Expected a number, z, c or '(', found {}",
+            self.peek()
+        ));
+        match self.next() {
+            Token::Number(n) => Ok(Expr::Number(Complex::new(n, 0.0))),
+            Token::LParen => {
+                let inner = self.sum()?;
+                self.expect(Token::RParen)?;
+                Ok(inner)
+            }
+            Token::Ident(name) => match name.as_str() {
+                "z" => Ok(Expr::Z),
+                "c" => Ok(Expr::C),
+                "i" => Ok(Expr::Number(Complex::new(0.0, 1.0))),
+                _ => {
+                    let Some(function) = Function::from_name(&name) else {
+                        self.pos -= 1;
+                        return Err(self.error(format!(
+                            "Unknown name '{name}', there are z, c, i and the functions {}",
+                            Function::NAMES.map(|(name, _)| name).join(", ")
+                        )));
+                    };
+                    self.expect(Token::LParen)?;
+                    let argument = self.sum()?;
+                    self.expect(Token::RParen)?;
+                    Ok(Expr::Call(function, Box::new(argument)))
+                }
+            },
+            _ => Err(error),
+        }
+    }
+}
+
+pub fn parse(src: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+        depth: 0,
+    };
+    let expr = parser.sum()?;
+    match parser.peek() {
+        Token::End => Ok(expr),
+        token => Err(parser.error(format!("Unexpected {token} after the formula"))),
+    }
+}
+
+impl Expr {
+    // The real integer exponents are the fastest and the most precise
+    fn real_exponent(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) if n.im == 0.0 => Some(n.re),
+            _ => None,
+        }
+    }
+
+    pub fn eval_f64(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        match self {
+            Self::Z => z,
+            Self::C => c,
+            Self::Number(n) => *n,
+            Self::Neg(a) => -a.eval_f64(z, c),
+            Self::Add(a, b) => a.eval_f64(z, c) + b.eval_f64(z, c),
+            Self::Sub(a, b) => a.eval_f64(z, c) - b.eval_f64(z, c),
+            Self::Mul(a, b) => a.eval_f64(z, c) * b.eval_f64(z, c),
+            Self::Div(a, b) => a.eval_f64(z, c) / b.eval_f64(z, c),
+            Self::Pow(a, b) => {
+                let base = a.eval_f64(z, c);
+                match b.real_exponent() {
+                    Some(x) if x.fract() == 0.0 && x.abs() <= i32::MAX as f64 => {
+                        base.powi_f64(x as i32)
+                    }
+                    Some(x) => base.powf_f64(x),
+                    None => base.powc_f64(b.eval_f64(z, c)),
+                }
+            }
+            Self::Call(function, a) => {
+                let a = a.eval_f64(z, c);
+                match function {
+                    Function::Sin => a.sin_f64(),
+                    Function::Cos => a.cos_f64(),
+                    Function::Tan => a.tan_f64(),
+                    Function::Sinh => a.sinh_f64(),
+                    Function::Cosh => a.cosh_f64(),
+                    Function::Tanh => a.tanh_f64(),
+                    Function::Exp => a.exp_f64(),
+                    Function::Ln => a.ln_f64(),
+                    Function::Sqrt => a.sqrt_f64(),
+                    Function::Recip => a.recip_f64(),
+                    Function::Conj => a.conj_f64(),
+                    Function::Abs => Complex::new(a.abs_f64(), 0.0),
+                    Function::Arg => Complex::new(a.arg_f64(), 0.0),
+                }
+            }
+        }
+    }
+
+    // At the precision of z, every step allocates
+    pub fn eval_rug(
+        &self,
+        z: &Complex<rug::Float>,
+        c: &Complex<rug::Float>,
+    ) -> Complex<rug::Float> {
+        let prec = z.re.prec();
+        match self {
+            Self::Z => z.clone(),
+            Self::C => c.clone(),
+            Self::Number(n) => Complex::new(
+                rug::Float::with_val(prec, n.re),
+                rug::Float::with_val(prec, n.im),
+            ),
+            Self::Neg(a) => -a.eval_rug(z, c),
+            Self::Add(a, b) => a.eval_rug(z, c) + b.eval_rug(z, c),
+            Self::Sub(a, b) => a.eval_rug(z, c) - b.eval_rug(z, c),
+            Self::Mul(a, b) => a.eval_rug(z, c).mul_rug(&b.eval_rug(z, c)),
+            Self::Div(a, b) => a.eval_rug(z, c).div_rug(&b.eval_rug(z, c)),
+            Self::Pow(a, b) => {
+                let base = a.eval_rug(z, c);
+                match b.real_exponent() {
+                    Some(x) if x.fract() == 0.0 && x.abs() <= i32::MAX as f64 => {
+                        base.powi_rug(x as i32)
+                    }
+                    Some(x) => base.powf_rug(&rug::Float::with_val(prec, x)),
+                    None => base.powc_rug(&b.eval_rug(z, c)),
+                }
+            }
+            Self::Call(function, a) => {
+                let a = a.eval_rug(z, c);
+                match function {
+                    Function::Sin => a.sin_rug(),
+                    Function::Cos => a.cos_rug(),
+                    Function::Tan => a.tan_rug(),
+                    Function::Sinh => a.sinh_rug(),
+                    Function::Cosh => a.cosh_rug(),
+                    Function::Tanh => a.tanh_rug(),
+                    Function::Exp => a.exp_rug(),
+                    Function::Ln => a.ln_rug(),
+                    Function::Sqrt => a.sqrt_rug(),
+                    Function::Recip => a.recip_rug(),
+                    Function::Conj => a.conj_rug(),
+                    Function::Abs => Complex::new(a.abs_rug(), rug::Float::new(prec)),
+                    Function::Arg => Complex::new(a.arg_rug(), rug::Float::new(prec)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(src: &str, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        parse(src).unwrap().eval_f64(z, c)
+    }
+
+    fn assert_close(a: Complex<f64>, b: Complex<f64>) {
+        let scale = b.norm_sqr_f64().sqrt().max(1.0);
+        assert!(
+            (a - b).norm_sqr_f64().sqrt() <= 1e-12 * scale,
+            "{} {} != {} {}",
+            a.re,
+            a.im,
+            b.re,
+            b.im
+        );
+    }
+
+    fn error(src: &str) -> ParseError {
+        match parse(src) {
+            Ok(_) => panic!("'{src}' parsed"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let Ok(Expr::Neg(inner)) = parse("-z^2") else {
+            panic!("-z^2 isn't a negation");
+        };
+        assert!(matches!(*inner, Expr::Pow(..)));
+        let i = Complex::new(0.0, 1.0);
+        assert_close(eval("-z^2", i, i), Complex::new(1.0, 0.0));
+
+        // z^(2^3), not (z^2)^3
+        let Ok(Expr::Pow(_, exponent)) = parse("z^2^3") else {
+            panic!("z^2^3 isn't a power");
+        };
+        assert!(matches!(*exponent, Expr::Pow(..)));
+        let two = Complex::new(2.0, 0.0);
+        assert_close(eval("z^3^2", two, two), Complex::new(512.0, 0.0));
+
+        assert_close(eval("1 + 2 * 3 - 4 / 2", two, two), Complex::new(5.0, 0.0));
+        assert_close(eval("z - c - 1", two, two), Complex::new(-1.0, 0.0));
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        assert!(matches!(parse("2z"), Ok(Expr::Mul(..))));
+        let z = Complex::new(2.0, 0.0);
+        let c = Complex::new(3.0, 0.0);
+        assert_close(eval("2z", z, c), Complex::new(4.0, 0.0));
+        assert_close(eval("z(z+1)", z, c), Complex::new(6.0, 0.0));
+        assert_close(eval("2z c", z, c), Complex::new(12.0, 0.0));
+        assert_close(eval("3i", z, c), Complex::new(0.0, 3.0));
+    }
+
+    #[test]
+    fn negative_exponents_are_folded() {
+        let Ok(Expr::Pow(_, exponent)) = parse("z^-2") else {
+            panic!("z^-2 isn't a power");
+        };
+        assert_eq!(exponent.real_exponent(), Some(-2.0));
+        let z = Complex::new(0.5, 0.5);
+        assert_close(eval("z^-2", z, z), (z * z).recip_f64());
+    }
+
+    #[test]
+    fn error_columns() {
+        let err = error("1.2.3");
+        assert_eq!(err.column, 1);
+        assert_eq!(err.message, "'1.2.3' is not a number");
+
+        // Names don't stop at a function name
+        let err = error("sinz");
+        assert_eq!(err.column, 1);
+        assert!(err.message.starts_with("Unknown name 'sinz'"));
+
+        let err = error("z + foo(z)");
+        assert_eq!(err.column, 5);
+        assert!(err.message.starts_with("Unknown name 'foo'"));
+
+        assert_eq!(error("sin z").column, 5);
+        assert_eq!(error("(z + c").column, 7);
+        assert_eq!(error("z + c)").column, 6);
+        assert_eq!(error("z # c").column, 3);
+        assert_eq!(error("2 * ").column, 5);
+        assert_eq!(error("").column, 1);
+        assert_eq!(
+            error("z^").to_string(),
+            "Column 3 : Expected a number, z, c or '(', found the end of the formula"
+        );
+    }
+
+    #[test]
+    fn nesting_is_capped() {
+        let deep = |open: &str, close: &str, n| open.repeat(n) + "z" + &close.repeat(n);
+        let too_deep = |src: &str| error(src).message.starts_with("Nested too deep");
+        assert!(parse(&deep("(", ")", MAX_DEPTH - 1)).is_ok());
+        assert!(too_deep(&deep("(", ")", MAX_DEPTH)));
+        assert!(too_deep(&deep("sin(", ")", MAX_DEPTH)));
+        assert!(too_deep(&deep("-", "", MAX_DEPTH)));
+        assert!(too_deep(&deep("z^", "", MAX_DEPTH)));
+
+        // Flat but as deep once it is a tree
+        let too_long = |src: &str| error(src).message.starts_with("The formula is too long");
+        assert!(too_long(&deep("z + ", "", MAX_TOKENS)));
+        assert!(too_long(&deep("(", ")", 10_000)));
+    }
+
+    #[test]
+    fn f64_and_rug_agree() {
+        let prec = 128;
+        let points = [
+            (Complex::new(0.3, -0.2), Complex::new(-0.7, 0.1)),
+            (Complex::new(-1.1, 0.6), Complex::new(0.25, 0.5)),
+            (Complex::new(0.05, 1.3), Complex::new(-1.5, -0.4)),
+        ];
+        for src in [
+            "z^2 + c",
+            "z^3 + c*sin(z)",
+            "z^-2 + c",
+            "z^2.5 + c",
+            "z^(1 + i) + c",
+            "exp(z)/(1 + z) - c",
+            "sqrt(z) + ln(c) + log(z)",
+            "cosh(z)*tanh(c) - sinh(z)/tan(c) + cos(z)",
+            "conj(z)^2 + recip(c) + abs(z) + arg(c)",
+        ] {
+            let expr = parse(src).unwrap();
+            for (z, c) in points {
+                let to_rug = |n: Complex<f64>| {
+                    Complex::new(
+                        rug::Float::with_val(prec, n.re),
+                        rug::Float::with_val(prec, n.im),
+                    )
+                };
+                let rug = expr.eval_rug(&to_rug(z), &to_rug(c)).to_f64();
+                assert_close(expr.eval_f64(z, c), rug);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_base_matches_mpc() {
+        let prec = 128;
+        let zero = Complex::new(0.0, 0.0);
+        let zero_rug = Complex::new(rug::Float::new(prec), rug::Float::new(prec));
+        for src in [
+            "z^2.5",
+            "z^(0.5 - i)",
+            "z^(0*i)",
+            "z^-2.5",
+            "z^(-1 + i)",
+            "z^(2*i)",
+        ] {
+            let expr = parse(src).unwrap();
+            let f64 = expr.eval_f64(zero, zero);
+            let rug = expr.eval_rug(&zero_rug, &zero_rug).to_f64();
+            match rug.re.is_finite() {
+                true => assert_close(f64, rug),
+                false => assert!(
+                    f64.re.is_infinite() == rug.re.is_infinite()
+                        && f64.re.is_nan() == rug.re.is_nan(),
+                    "{src} : {} {} != {} {}",
+                    f64.re,
+                    f64.im,
+                    rug.re,
+                    rug.im
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use rug::{Complex, Float};
+
+// Precision of the radii and of the size, only their magnitude matters but
+// they go way past what an f64 can hold at deep zooms
+const RADIUS_PREC: u32 = 53;
+// Past this |z| the ball has left for good
+const BALL_ESCAPE: f64 = 4.0;
+const NEWTON_MAX_STEPS: u32 = 64;
+// The nucleus is found when Newton's step is this many bits above the precision
+const NEWTON_EXP_MARGIN: i32 = 8;
+
+// A minibrot of the z² + c Mandelbrot set : the c of its period p cycle going
+// through 0, and about how far its cardioid goes from there
+pub struct Minibrot {
+    pub nucleus: Complex,
+    pub period: u32,
+    pub size: Float,
+}
+
+impl Minibrot {
+    // The minibrot of lowest period in the ball of center c, the nucleus is
+    // computed at the precision of c
+    pub fn find(c: &Complex, radius: &Float, max_period: u32) -> Option<Self> {
+        let period = ball_period(c, radius, max_period)?;
+        let nucleus = nucleus(c, period)?;
+        let size = size(&nucleus, period);
+        Some(Self {
+            nucleus,
+            period,
+            size,
+        })
+    }
+}
+
+// Ball method : the ball around c is iterated along with z, the first time
+// the ball around z_n contains 0 a period n nucleus is in the starting ball
+// r' = r*(2|z| + r) + radius bounds how much the ball grows at every step
+fn ball_period(c: &Complex, radius: &Float, max_period: u32) -> Option<u32> {
+    let mut z = Complex::new(c.prec());
+    let mut r = Float::new(RADIUS_PREC);
+    for period in 1..=max_period {
+        let mut next_r = Float::with_val(RADIUS_PREC, z.abs_ref());
+        next_r *= 2;
+        next_r += &r;
+        next_r *= &r;
+        next_r += radius;
+        r = next_r;
+        z.square_mut();
+        z += c;
+
+        let z_abs = Float::with_val(RADIUS_PREC, z.abs_ref());
+        if z_abs < r {
+            return Some(period);
+        }
+        if z_abs > BALL_ESCAPE {
+            return None;
+        }
+    }
+    None
+}
+
+// Newton's method on z_p(c) = 0, with dz/dc' = 2*z*dz/dc + 1
+fn nucleus(c0: &Complex, period: u32) -> Option<Complex> {
+    let prec = c0.prec();
+    let mut c = c0.clone();
+    for _ in 0..NEWTON_MAX_STEPS {
+        let mut z = Complex::new(prec);
+        let mut dz = Complex::new(prec);
+        for _ in 0..period {
+            dz *= &z;
+            dz *= 2;
+            dz += 1;
+            z.square_mut();
+            z += &c;
+        }
+        z /= &dz;
+        c -= &z;
+
+        let step = Float::with_val(RADIUS_PREC, z.abs_ref());
+        if !step.is_finite() {
+            return None;
+        }
+        match step.get_exp() {
+            None => return Some(c),
+            Some(exp) if exp < NEWTON_EXP_MARGIN - prec.0 as i32 => return Some(c),
+            _ => (),
+        }
+    }
+    None
+}
+
+// |1 / (b*l²)|, l being the derivative of the cycle and b the sum of 1/l
+// along it
+fn size(nucleus: &Complex, period: u32) -> Float {
+    let prec = nucleus.prec();
+    let mut z = Complex::new(prec);
+    let mut l = Complex::with_val(prec, 1);
+    let mut b = Complex::with_val(prec, 1);
+    for _ in 1..period {
+        z.square_mut();
+        z += nucleus;
+        l *= &z;
+        l *= 2;
+        b += Complex::with_val(prec, l.recip_ref());
+    }
+    l.square_mut();
+    l *= &b;
+    let mut size = Float::with_val(RADIUS_PREC, l.abs_ref());
+    size.recip_mut();
+    size
+}
+
+// The longest orbits tried when guessing the preperiod and the period
+const MISIUREWICZ_MAX_PREPERIOD: u32 = 32;
+const MISIUREWICZ_MAX_PERIOD: u32 = 32;
+// How many of the best guesses get Newton's method
+const MISIUREWICZ_CANDIDATES: usize = 16;
+
+// A Misiurewicz point of the z² + c Mandelbrot set : the orbit of 0 falls on
+// a cycle of `period` after `preperiod` iterations, z_(q+p) = z_q
+#[derive(Clone)]
+pub struct Misiurewicz {
+    pub point: Complex,
+    pub preperiod: u32,
+    pub period: u32,
+}
+
+impl Misiurewicz {
+    // The orbit of c gets back close to where it was for the (q, p) of the
+    // Misiurewicz points around it, the pairs that come the closest are tried
+    // until one lands in the ball of center c
+    pub fn find(c: &Complex, radius: &Float) -> Option<Self> {
+        let max_iter = MISIUREWICZ_MAX_PREPERIOD + MISIUREWICZ_MAX_PERIOD;
+        let mut orbit = vec![Complex::new(RADIUS_PREC)];
+        let mut z = Complex::new(RADIUS_PREC);
+        for _ in 0..max_iter {
+            z.square_mut();
+            z += c;
+            if Float::with_val(RADIUS_PREC, z.abs_ref()) > BALL_ESCAPE {
+                break;
+            }
+            orbit.push(z.clone());
+        }
+
+        let mut guesses = Vec::new();
+        for preperiod in 1..=MISIUREWICZ_MAX_PREPERIOD {
+            for period in 1..=MISIUREWICZ_MAX_PERIOD {
+                let Some(z_qp) = orbit.get((preperiod + period) as usize) else {
+                    break;
+                };
+                let diff = Complex::with_val(RADIUS_PREC, z_qp - &orbit[preperiod as usize]);
+                guesses.push((
+                    Float::with_val(RADIUS_PREC, diff.abs_ref()),
+                    preperiod,
+                    period,
+                ));
+            }
+        }
+        guesses.sort_by(|(a, _, _), (b, _, _)| a.total_cmp(b));
+
+        // Different guesses can land on different points, the closest one wins
+        guesses
+            .iter()
+            .take(MISIUREWICZ_CANDIDATES)
+            .filter_map(|&(_, preperiod, period)| {
+                let point = misiurewicz_point(c, preperiod, period)?;
+                let distance = Float::with_val(
+                    RADIUS_PREC,
+                    Complex::with_val(c.prec(), &point - c).abs_ref(),
+                );
+                (distance < *radius).then_some((distance, point, preperiod, period))
+            })
+            .min_by(|(a, ..), (b, ..)| a.total_cmp(b))
+            .map(|(_, point, preperiod, period)| Self {
+                period: lowest_period(&point, preperiod, period),
+                point,
+                preperiod,
+            })
+    }
+}
+
+// z_0..=z_n and their dz/dc
+fn orbit_with_derivative(c: &Complex, n: u32) -> (Vec<Complex>, Vec<Complex>) {
+    let prec = c.prec();
+    let mut zs = vec![Complex::new(prec)];
+    let mut dzs = vec![Complex::new(prec)];
+    for i in 0..n as usize {
+        let mut dz = Complex::with_val(prec, &zs[i] * &dzs[i]);
+        dz *= 2;
+        dz += 1;
+        let mut z = zs[i].clone();
+        z.square_mut();
+        z += c;
+        zs.push(z);
+        dzs.push(dz);
+    }
+    (zs, dzs)
+}
+
+// Newton's method on (z_(q+p) - z_q) / prod(z_(i+p) - z_i) for i < q, the
+// division keeps it from landing on the points of lower preperiod
+// The step is 1 / (f'/f - sum(h_i'/h_i))
+fn misiurewicz_point(c0: &Complex, preperiod: u32, period: u32) -> Option<Complex> {
+    let prec = c0.prec();
+    let mut c = c0.clone();
+    for _ in 0..NEWTON_MAX_STEPS {
+        let (zs, dzs) = orbit_with_derivative(&c, preperiod + period);
+        let ratio = |i: usize| {
+            let h = Complex::with_val(prec, &zs[i + period as usize] - &zs[i]);
+            let mut dh = Complex::with_val(prec, &dzs[i + period as usize] - &dzs[i]);
+            dh /= &h;
+            dh
+        };
+
+        let mut step = ratio(preperiod as usize);
+        for i in 0..preperiod as usize {
+            step -= ratio(i);
+        }
+        step.recip_mut();
+        c -= &step;
+
+        let step = Float::with_val(RADIUS_PREC, step.abs_ref());
+        if !step.is_finite() {
+            return None;
+        }
+        match step.get_exp() {
+            None => return Some(c),
+            Some(exp) if exp < NEWTON_EXP_MARGIN - prec.0 as i32 => return Some(c),
+            _ => (),
+        }
+    }
+    None
+}
+
+// The period found can be a multiple of the real one
+fn lowest_period(point: &Complex, preperiod: u32, period: u32) -> u32 {
+    let (zs, _) = orbit_with_derivative(point, preperiod + period);
+    // Half of the precision, the orbit drifts away from the cycle since it repels
+    let tolerance_exp = -(point.prec().0 as i32) / 2;
+    (1..period)
+        .filter(|&divisor| period.is_multiple_of(divisor))
+        .find(|&divisor| {
+            let diff = Complex::with_val(
+                point.prec(),
+                &zs[(preperiod + divisor) as usize] - &zs[preperiod as usize],
+            );
+            let diff = Float::with_val(RADIUS_PREC, diff.abs_ref());
+            diff.get_exp().is_none_or(|exp| exp < tolerance_exp)
+        })
+        .unwrap_or(period)
+}
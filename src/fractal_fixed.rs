@@ -0,0 +1,300 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+use rug::integer::Order;
+
+use crate::{
+    fractal_complex::{Complex, FractalFloat},
+    fractal_engine::EscapeNorm,
+};
+
+// Bits of the integer part, z has to stay under 2^(FIXED_INT_BITS - 1) until
+// it escapes
+pub const FIXED_INT_BITS: u32 = 16;
+// 512 bits, MPFR is used past that
+pub const FIXED_MAX_LIMBS: usize = 8;
+// Shift of the top half of a product to get back to the fixed scale
+const PRODUCT_SHIFT: u32 = 64 - FIXED_INT_BITS;
+
+// Fixed-point number of N 64 bits limbs, the lowest limb first in two's
+// complement. It is the integer of the limbs divided by 2^(64*N - FIXED_INT_BITS)
+// There is no exponent so nothing allocates and nothing calls GMP
+#[derive(Clone, Copy)]
+pub struct Fixed<const N: usize> {
+    limbs: [u64; N],
+}
+
+impl<const N: usize> Fixed<N> {
+    pub const ZERO: Self = Self { limbs: [0; N] };
+    const FRAC_BITS: u32 = 64 * N as u32 - FIXED_INT_BITS;
+
+    fn is_negative(&self) -> bool {
+        self.limbs[N - 1] >> 63 == 1
+    }
+
+    pub fn abs(self) -> Self {
+        match self.is_negative() {
+            true => -self,
+            false => self,
+        }
+    }
+
+    // 2x, for the 2*re*im of the formulas
+    pub fn double(self) -> Self {
+        let mut limbs = [0; N];
+        let mut carry = 0;
+        for (limb, n) in limbs.iter_mut().zip(self.limbs) {
+            *limb = (n << 1) | carry;
+            carry = n >> 63;
+        }
+        Self { limbs }
+    }
+
+    // Exact, f64 has less bits than the fraction. The pixel fractions are
+    // between -0.5 and 0.5
+    pub fn from_f64(n: f64) -> Self {
+        let mut magnitude = n.abs();
+        let mut limbs = [0; N];
+        for (i, limb) in limbs.iter_mut().enumerate().rev() {
+            let unit = 2f64.powi(64 * i as i32 - Self::FRAC_BITS as i32);
+            let digit = (magnitude / unit).floor();
+            *limb = digit as u64;
+            magnitude -= digit * unit;
+        }
+        match n < 0.0 {
+            true => -Self { limbs },
+            false => Self { limbs },
+        }
+    }
+}
+
+impl<const N: usize> Add for Fixed<N> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        let mut limbs = [0; N];
+        let mut carry = false;
+        for ((limb, a), b) in limbs.iter_mut().zip(self.limbs).zip(rhs.limbs) {
+            let (sum, carry_a) = a.overflowing_add(b);
+            let (sum, carry_b) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = carry_a || carry_b;
+        }
+        Self { limbs }
+    }
+}
+
+impl<const N: usize> Neg for Fixed<N> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        let mut limbs = self.limbs.map(|limb| !limb);
+        for limb in &mut limbs {
+            let (sum, carry) = limb.overflowing_add(1);
+            *limb = sum;
+            if !carry {
+                break;
+            }
+        }
+        Self { limbs }
+    }
+}
+
+impl<const N: usize> Sub for Fixed<N> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl<const N: usize> Mul for Fixed<N> {
+    type Output = Self;
+
+    // Schoolbook on the magnitudes, the product has twice the fraction bits
+    // so its limbs N-1 to 2N-1 are shifted back down
+    fn mul(self, rhs: Self) -> Self {
+        let negative = self.is_negative() != rhs.is_negative();
+        let (a, b) = (self.abs().limbs, rhs.abs().limbs);
+        let mut product = [0u64; 2 * FIXED_MAX_LIMBS];
+        for i in 0..N {
+            let mut carry = 0;
+            for j in 0..N {
+                let t = a[i] as u128 * b[j] as u128 + product[i + j] as u128 + carry;
+                product[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            product[i + N] = carry as u64;
+        }
+
+        let mut limbs = [0; N];
+        for (k, limb) in limbs.iter_mut().enumerate() {
+            *limb =
+                (product[N - 1 + k] >> PRODUCT_SHIFT) | (product[N + k] << (64 - PRODUCT_SHIFT));
+        }
+        match negative {
+            true => -Self { limbs },
+            false => Self { limbs },
+        }
+    }
+}
+
+impl<const N: usize> FractalFloat for Fixed<N> {
+    type Scratch = ();
+
+    fn scratch(_prec: u32) {}
+
+    // Rounded to the closest, NaN and infinities give 0
+    fn from_rug(n: &rug::Float) -> Self {
+        let mut scaled = n.clone();
+        scaled <<= Self::FRAC_BITS;
+        let Some(integer) = scaled.to_integer() else {
+            return Self::ZERO;
+        };
+        let mut limbs = [0; N];
+        for (limb, digit) in limbs.iter_mut().zip(integer.to_digits::<u64>(Order::Lsf)) {
+            *limb = digit;
+        }
+        match integer.is_negative() {
+            true => -Self { limbs },
+            false => Self { limbs },
+        }
+    }
+
+    // The two highest limbs are more than what f64 holds
+    fn to_f64(&self) -> f64 {
+        let magnitude = self.abs().limbs;
+        let high = magnitude[N - 1] as f64 * 2f64.powi(FIXED_INT_BITS as i32 - 64);
+        let low = magnitude[N - 2] as f64 * 2f64.powi(FIXED_INT_BITS as i32 - 128);
+        match self.is_negative() {
+            true => -(high + low),
+            false => high + low,
+        }
+    }
+
+    fn assign_from(&mut self, n: &Self) {
+        *self = *n;
+    }
+
+    fn map_fraction(&mut self, center: &Self, window: &Self, fraction: f64) {
+        *self = *center + *window * Self::from_f64(fraction - 0.5);
+    }
+
+    fn abs_in_place(&mut self) {
+        *self = self.abs();
+    }
+
+    fn neg_in_place(&mut self) {
+        *self = -*self;
+    }
+
+    fn square(z: &mut Complex<Self>, _: &mut ()) {
+        (z.re, z.im) = (z.re * z.re - z.im * z.im, (z.re * z.im).double());
+    }
+
+    fn powu(z: &mut Complex<Self>, n: u32, _: &mut ()) {
+        *z = z.powu(n);
+    }
+
+    fn add_in_place(&mut self, n: &Self) {
+        *self = *self + *n;
+    }
+}
+
+// The last z before escaping is at most sqrt(2) times the converge distance
+// away from 0 (more with the weighted norm), the first one is the pixel, which
+// is further when zoomed out. z^n + c has to fit in the integer part, half of
+// it is kept for the additions
+pub fn fixed_point_fits(
+    escape_norm: EscapeNorm,
+    converge_distance: f64,
+    power: u32,
+    pixel_abs: f64,
+    c_abs: f64,
+) -> bool {
+    if let EscapeNorm::Weighted(..) = escape_norm {
+        return false;
+    }
+    let z_abs = (converge_distance * std::f64::consts::SQRT_2).max(pixel_abs);
+    let range = 2f64.powi(FIXED_INT_BITS as i32 - 2);
+    z_abs.powi(power as i32) + c_abs < range
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::{Integer, integer::Order};
+
+    use super::*;
+    use crate::fractal_buddhabrot::XorShift;
+
+    const SAMPLES: usize = 1000;
+
+    // Exact, the limbs are the two's complement integer of the fraction
+    fn to_rug<const N: usize>(n: Fixed<N>) -> rug::Float {
+        let mut integer = Integer::from_digits(&n.limbs, Order::Lsf);
+        if n.is_negative() {
+            integer -= Integer::from(1) << (64 * N as u32);
+        }
+        rug::Float::with_val(64 * N as u32, integer) >> Fixed::<N>::FRAC_BITS
+    }
+
+    // In (-2^7, 2^7), with more bits than the fraction holds
+    fn random_rug<const N: usize>(rng: &mut XorShift) -> rug::Float {
+        let limbs: Vec<u64> = (0..N).map(|_| rng.next_u64()).collect();
+        let n = rug::Float::with_val(64 * N as u32, Integer::from_digits(&limbs, Order::Lsf))
+            >> (64 * N as u32 - 7);
+        match rng.next_u64() & 1 {
+            0 => n,
+            _ => -n,
+        }
+    }
+
+    fn ulp<const N: usize>() -> rug::Float {
+        rug::Float::with_val(64, 1) >> Fixed::<N>::FRAC_BITS
+    }
+
+    fn check_against_rug<const N: usize>() {
+        let mut rng = XorShift::new(N as u64);
+        let prec = 4 * 64 * N as u32;
+        for _ in 0..SAMPLES {
+            let (x, y) = (random_rug::<N>(&mut rng), random_rug::<N>(&mut rng));
+            let (a, b) = (Fixed::<N>::from_rug(&x), Fixed::<N>::from_rug(&y));
+            let (a_rug, b_rug) = (to_rug(a), to_rug(b));
+
+            // Rounded to the closest
+            let err = rug::Float::with_val(prec, &a_rug - &x).abs();
+            assert!(err <= ulp::<N>() >> 1u32, "from_rug {x}");
+
+            assert_eq!(to_rug(a + b), rug::Float::with_val(prec, &a_rug + &b_rug));
+            assert_eq!(to_rug(a - b), rug::Float::with_val(prec, &a_rug - &b_rug));
+            assert_eq!(to_rug(-a), rug::Float::with_val(prec, -&a_rug));
+
+            // Truncated, the bits under the fraction are dropped
+            let product = rug::Float::with_val(prec, &a_rug * &b_rug);
+            let err = rug::Float::with_val(prec, to_rug(a * b) - &product).abs();
+            assert!(err < ulp::<N>(), "{a_rug} * {b_rug}");
+
+            let expected = a_rug.to_f64();
+            assert!((a.to_f64() - expected).abs() <= expected.abs() * f64::EPSILON);
+        }
+    }
+
+    #[test]
+    fn two_limbs() {
+        check_against_rug::<2>();
+    }
+
+    #[test]
+    fn eight_limbs() {
+        check_against_rug::<8>();
+    }
+
+    #[test]
+    fn zoomed_out_doesnt_fit() {
+        let fits =
+            |pixel_abs, c_abs| fixed_point_fits(EscapeNorm::Euclidean, 2.0, 2, pixel_abs, c_abs);
+        assert!(fits(2.0, 2.0));
+        // The first square overflows even if c doesn't
+        assert!(!fits(200.0, 2.0));
+        assert!(!fits(2.0, 20000.0));
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    cmp::Ordering,
+    ops::{Add, Mul, Neg, Sub},
+};
+
+use crate::{fractal_complex::Complex, fractal_engine::EscapeNorm};
+
+// Past this difference of exponents the smaller number doesn't change the sum
+const ADD_MAX_EXP_DIFF: i64 = 64;
+
+// mant * 2^exp, with 0.5 <= |mant| < 1 (or mant = 0 and exp = 0)
+// Same precision as an f64 but the exponent doesn't run out past 1e-308
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FloatExp {
+    mant: f64,
+    exp: i64,
+}
+
+// f64::frexp doesn't exist...
+fn frexp(n: f64) -> (f64, i64) {
+    if n == 0.0 || !n.is_finite() {
+        return (n, 0);
+    }
+    let bits = n.to_bits();
+    let biased_exp = ((bits >> 52) & 0x7FF) as i64;
+    if biased_exp == 0 {
+        // Subnormal, make it normal first
+        let (mant, exp) = frexp(n * f64::from_bits(0x4350_0000_0000_0000)); // 2^54
+        return (mant, exp - 54);
+    }
+    let mant = f64::from_bits((bits & 0x800F_FFFF_FFFF_FFFF) | 0x3FE0_0000_0000_0000);
+    (mant, biased_exp - 1022)
+}
+
+// mant * 2^exp for an exp that can be way outside of what f64 can hold
+fn ldexp(mant: f64, exp: i64) -> f64 {
+    let exp = exp.clamp(-2200, 2200) as i32;
+    // Two steps so that 2^(exp/2) doesn't overflow before the result does
+    mant * 2f64.powi(exp / 2) * 2f64.powi(exp - exp / 2)
+}
+
+impl FloatExp {
+    pub const ZERO: Self = Self { mant: 0.0, exp: 0 };
+
+    pub fn new(mant: f64, exp: i64) -> Self {
+        let (mant, mant_exp) = frexp(mant);
+        if mant == 0.0 {
+            return Self::ZERO;
+        }
+        Self {
+            mant,
+            exp: exp + mant_exp,
+        }
+    }
+
+    pub fn from_f64(n: f64) -> Self {
+        Self::new(n, 0)
+    }
+
+    pub fn from_rug(n: &rug::Float) -> Self {
+        let (mant, exp) = n.to_f64_exp();
+        Self::new(mant, exp as i64)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        ldexp(self.mant, self.exp)
+    }
+
+    // Zero has no exponent, it is smaller than anything
+    pub fn exp(self) -> i64 {
+        match self.mant == 0.0 {
+            true => i64::MIN,
+            false => self.exp,
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        Self {
+            mant: self.mant.abs(),
+            exp: self.exp,
+        }
+    }
+
+    pub fn sqrt(self) -> Self {
+        // Keeps the exponent even so that it can be halved
+        let (mant, exp) = if self.exp % 2 == 0 {
+            (self.mant, self.exp)
+        } else {
+            (self.mant * 2.0, self.exp - 1)
+        };
+        Self::new(mant.sqrt(), exp / 2)
+    }
+}
+
+impl Add for FloatExp {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        if self.mant == 0.0 {
+            return rhs;
+        }
+        if rhs.mant == 0.0 {
+            return self;
+        }
+        let exp_diff = self.exp - rhs.exp;
+        if exp_diff > ADD_MAX_EXP_DIFF {
+            self
+        } else if exp_diff < -ADD_MAX_EXP_DIFF {
+            rhs
+        } else if exp_diff >= 0 {
+            Self::new(self.mant + ldexp(rhs.mant, -exp_diff), self.exp)
+        } else {
+            Self::new(ldexp(self.mant, exp_diff) + rhs.mant, rhs.exp)
+        }
+    }
+}
+
+impl Neg for FloatExp {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self {
+            mant: -self.mant,
+            exp: self.exp,
+        }
+    }
+}
+
+impl Sub for FloatExp {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl Mul for FloatExp {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(self.mant * rhs.mant, self.exp + rhs.exp)
+    }
+}
+
+impl PartialEq for FloatExp {
+    fn eq(&self, other: &Self) -> bool {
+        self.mant == other.mant && self.exp == other.exp
+    }
+}
+
+impl PartialOrd for FloatExp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.mant.is_sign_negative() != other.mant.is_sign_negative()
+            || self.mant == 0.0
+            || other.mant == 0.0
+        {
+            return self.mant.partial_cmp(&other.mant);
+        }
+        let ord = self
+            .exp
+            .cmp(&other.exp)
+            .then(self.mant.abs().total_cmp(&other.mant.abs()));
+        match self.mant.is_sign_negative() {
+            true => Some(ord.reverse()),
+            false => Some(ord),
+        }
+    }
+}
+
+impl Complex<FloatExp> {
+    pub fn from_rug_floatexp(n: &rug::Complex) -> Self {
+        Self::new(FloatExp::from_rug(n.real()), FloatExp::from_rug(n.imag()))
+    }
+
+    pub fn from_f64_floatexp(n: Complex<f64>) -> Self {
+        Self::new(FloatExp::from_f64(n.re), FloatExp::from_f64(n.im))
+    }
+
+    pub fn to_f64(self) -> Complex<f64> {
+        Complex::new(self.re.to_f64(), self.im.to_f64())
+    }
+
+    pub fn map_pixel_value_floatexp(
+        res: Complex<f64>,
+        center: Self,
+        window: Self,
+        coord: Complex<f64>,
+    ) -> Self {
+        let half = FloatExp::from_f64(0.5);
+        Self::new(
+            center.re - window.re * half + FloatExp::from_f64(coord.re / res.re) * window.re,
+            center.im - window.im * half
+                + FloatExp::from_f64((res.im - coord.im) / res.im) * window.im,
+        )
+    }
+
+    pub fn abs_sum_floatexp(&self) -> f64 {
+        (self.re.abs() + self.im.abs()).to_f64()
+    }
+
+    pub fn norm_sqr_floatexp(&self) -> FloatExp {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn distance_floatexp(&self, norm: EscapeNorm) -> f64 {
+        match norm {
+            EscapeNorm::L1 => self.abs_sum_floatexp(),
+            EscapeNorm::Euclidean => self.norm_sqr_floatexp().to_f64(),
+            EscapeNorm::LInf | EscapeNorm::Weighted(_, _) => self.to_f64().distance_f64(norm),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::fractal_complex::Complex;
+
+// Iterations thrown away before measuring, x has to settle first
+const LYAPUNOV_WARMUP: u32 = 50;
+const LYAPUNOV_X0: f64 = 0.5;
+// Exponents past these are drawn at full brightness
+const LYAPUNOV_STABLE_SCALE: f64 = 2.0;
+const LYAPUNOV_CHAOTIC_SCALE: f64 = 1.0;
+
+// "AABAB" -> the r to use at every step, true for b
+// Anything else than A and B is ignored, an empty sequence is "AB"
+pub fn parse_sequence(seq: &str) -> Vec<bool> {
+    let seq: Vec<bool> = seq
+        .chars()
+        .filter_map(|c| match c.to_ascii_uppercase() {
+            'A' => Some(false),
+            'B' => Some(true),
+            _ => None,
+        })
+        .collect();
+    match seq.is_empty() {
+        true => vec![false, true],
+        false => seq,
+    }
+}
+
+// Logistic map x' = r*x*(1 - x) with r going through the sequence,
+// the exponent is the mean of ln|r*(1 - 2x)|
+// The pixel is (a, b) = (re, im)
+pub fn lyapunov_exponent(ab: Complex<f64>, seq: &[bool], iters: u32) -> f64 {
+    let r_at = |n: u32| match seq[n as usize % seq.len()] {
+        true => ab.im,
+        false => ab.re,
+    };
+
+    let mut x = LYAPUNOV_X0;
+    for n in 0..LYAPUNOV_WARMUP {
+        let r = r_at(n);
+        x = r * x * (1.0 - x);
+    }
+
+    let mut sum = 0.0;
+    for n in LYAPUNOV_WARMUP..LYAPUNOV_WARMUP + iters {
+        let r = r_at(n);
+        x = r * x * (1.0 - x);
+        sum += (r * (1.0 - 2.0 * x)).abs().ln();
+    }
+    sum / iters as f64
+}
+
+// Stable (negative) in yellow, chaotic (positive) in blue, both black at 0
+pub fn lyapunov_color(exponent: f64) -> [u8; 4] {
+    if !exponent.is_finite() {
+        return [0, 0, 0, 255];
+    }
+    if exponent <= 0.0 {
+        let t = (-exponent / LYAPUNOV_STABLE_SCALE).min(1.0);
+        [(t * 255.0) as u8, (t * 220.0) as u8, 0, 255]
+    } else {
+        let t = (exponent / LYAPUNOV_CHAOTIC_SCALE).min(1.0);
+        [0, (t * 60.0) as u8, (t * 255.0) as u8, 255]
+    }
+}
@@ -0,0 +1,185 @@
+use crate::fractal_complex::Complex;
+
+// |step|² under which z is considered converged
+const NEWTON_TOLERANCE_SQ: f64 = 1e-12;
+// How close to a root z has to be to be colored by it
+const ROOT_TOLERANCE_SQ: f64 = 1e-6;
+const DURAND_KERNER_ITERS: u32 = 500;
+// Every iteration darkens the root's color by that much
+const NEWTON_SHADE_BASE: f64 = 0.95;
+
+// p(z) = coeffs[0] + coeffs[1]*z + ... + coeffs[n]*z^n
+// The roots are found once with Durand-Kerner, then every pixel runs Newton's
+// method and gets the color of the root it lands on
+pub struct NewtonPolynomial {
+    coeffs: Vec<Complex<f64>>,
+    roots: Vec<Complex<f64>>,
+}
+
+impl NewtonPolynomial {
+    pub fn new(coeffs: &[Complex<f64>]) -> Self {
+        // The highest coefficients at 0 don't count in the degree
+        let degree = coeffs
+            .iter()
+            .rposition(|c| c.norm_sqr_f64() != 0.0)
+            .unwrap_or(0);
+        let mut poly = Self {
+            coeffs: coeffs[..=degree].to_vec(),
+            roots: Vec::new(),
+        };
+        poly.roots = poly.durand_kerner();
+        poly
+    }
+
+    fn degree(&self) -> usize {
+        self.coeffs.len() - 1
+    }
+
+    // p(z) and p'(z) at once, Horner's way
+    fn eval_f64(&self, z: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+        let mut p = self.coeffs[self.degree()];
+        let mut dp = Complex::new(0.0, 0.0);
+        for &coeff in self.coeffs[..self.degree()].iter().rev() {
+            dp = dp * z + p;
+            p = p * z + coeff;
+        }
+        (p, dp)
+    }
+
+    fn durand_kerner(&self) -> Vec<Complex<f64>> {
+        let degree = self.degree();
+        if degree == 0 {
+            return Vec::new();
+        }
+        let leading = self.coeffs[degree];
+        // Not a root of unity and not real, the usual starting points
+        let seed = Complex::new(0.4, 0.9);
+        let mut roots: Vec<Complex<f64>> = (0..degree as u32).map(|k| seed.powu(k + 1)).collect();
+
+        for _ in 0..DURAND_KERNER_ITERS {
+            let mut moved: f64 = 0.0;
+            for i in 0..degree {
+                let mut denom = leading;
+                for j in 0..degree {
+                    if i != j {
+                        denom = denom * (roots[i] - roots[j]);
+                    }
+                }
+                let step = self.eval_f64(roots[i]).0 / denom;
+                roots[i] = roots[i] - step;
+                moved = moved.max(step.norm_sqr_f64());
+            }
+            if moved < NEWTON_TOLERANCE_SQ * NEWTON_TOLERANCE_SQ {
+                break;
+            }
+        }
+        roots
+    }
+
+    // Newton's method from z, colored by the root it lands on
+    pub fn color<Z: NewtonComplex>(&self, mut z: Z, seq_iter: u32) -> [u8; 4] {
+        let mut root = None;
+        let mut iter = 0;
+        while iter < seq_iter {
+            let step = z.newton_step(self);
+            iter += 1;
+            if step < NEWTON_TOLERANCE_SQ {
+                root = self.classify_root(z.to_c64());
+                break;
+            }
+        }
+        self.root_color(root, iter)
+    }
+
+    // z -= p(z)/p'(z), returns |p(z)/p'(z)|²
+    fn step_f64(&self, z: &mut Complex<f64>) -> f64 {
+        let (p, dp) = self.eval_f64(*z);
+        let step = p / dp;
+        *z = *z - step;
+        step.norm_sqr_f64()
+    }
+
+    fn step_rug(&self, z: &mut rug::Complex) -> f64 {
+        let last = self.coeffs[self.degree()];
+        let mut p = rug::Complex::with_val(z.prec(), (last.re, last.im));
+        let mut dp = rug::Complex::new(z.prec());
+        for coeff in self.coeffs[..self.degree()].iter().rev() {
+            dp *= &*z;
+            dp += &p;
+            p *= &*z;
+            *p.mut_real() += coeff.re;
+            *p.mut_imag() += coeff.im;
+        }
+        p /= &dp;
+        *z -= &p;
+        Complex::from_rug_f64(&p).norm_sqr_f64()
+    }
+
+    // Same for every backend, z only has to be close to the root so f64 is enough
+    fn classify_root(&self, z: Complex<f64>) -> Option<usize> {
+        self.roots
+            .iter()
+            .map(|root| (*root - z).norm_sqr_f64())
+            .enumerate()
+            .filter(|(_, dist)| *dist < ROOT_TOLERANCE_SQ)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(id, _)| id)
+    }
+
+    // One hue per root, darker the longer it took, black if it never converged
+    fn root_color(&self, root: Option<usize>, iter: u32) -> [u8; 4] {
+        let Some(root) = root else {
+            return [0, 0, 0, 255];
+        };
+        let hue = root as f64 / self.roots.len() as f64;
+        let value = NEWTON_SHADE_BASE.powi(iter as i32);
+        hue_color(hue, value)
+    }
+}
+
+// What the pixels run Newton's method with, f64 or Rug
+pub trait NewtonComplex {
+    // z -= p(z)/p'(z), returns |p(z)/p'(z)|²
+    fn newton_step(&mut self, poly: &NewtonPolynomial) -> f64;
+    fn to_c64(&self) -> Complex<f64>;
+}
+
+impl NewtonComplex for Complex<f64> {
+    fn newton_step(&mut self, poly: &NewtonPolynomial) -> f64 {
+        poly.step_f64(self)
+    }
+
+    fn to_c64(&self) -> Complex<f64> {
+        *self
+    }
+}
+
+impl NewtonComplex for rug::Complex {
+    fn newton_step(&mut self, poly: &NewtonPolynomial) -> f64 {
+        poly.step_rug(self)
+    }
+
+    fn to_c64(&self) -> Complex<f64> {
+        Complex::from_rug_f64(self)
+    }
+}
+
+// HSV with full saturation, hue in 0..1
+fn hue_color(hue: f64, value: f64) -> [u8; 4] {
+    let h = hue * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    [
+        (r * value * 255.0) as u8,
+        (g * value * 255.0) as u8,
+        (b * value * 255.0) as u8,
+        255,
+    ]
+}
@@ -0,0 +1,496 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::{
+    fractal_coloring::{Coloring, OrbitStats},
+    fractal_complex::{Complex, FractalFloat},
+    fractal_engine::{EscapeNorm, FractalContext, FractalFormula},
+    fractal_floatexp::FloatExp,
+};
+
+// How big the cubic term can get compared to the quadratic one before the
+// series approximation is not trusted anymore
+const SERIES_TOLERANCE: f64 = 1e-6;
+
+// Below this exponent a delta is kept as a FloatExp, f64 would lose it
+const DELTA_F64_MIN_EXP: i64 = -900;
+
+// Z_0 to Z_2 : the delta loop starts at Z_1 for the Mandelbrot set (Z_0 for
+// the Julia sets) and steps to the next one before it can rebase
+const MIN_ORBIT_LEN: usize = 3;
+
+// One high precision orbit at the center of the view, every pixel is then
+// computed as a small f64 delta against it (see `compute_image_perturbation`)
+// For the Julia sets the reference starts at the center instead of 0 and the
+// deltas don't get dc added, the pixel offset is dz_0
+#[derive(Default)]
+pub struct ReferenceOrbit {
+    pub orbit: Vec<Complex<f64>>,
+    // Julia sets only, the orbit of 0 : the deltas are rebased on it since
+    // `orbit` doesn't go through 0
+    critical_orbit: Vec<Complex<f64>>,
+    pub series: SeriesApprox,
+    seq_iter: u32,
+    bailout: f64,
+    escape_norm: EscapeNorm,
+    julia: bool,
+    formula: FractalFormula,
+    c: Complex<f64>, // the c of the reference, the Julia one for the Julia sets
+    coloring: Coloring,
+}
+
+// |c + d| - |c| without losing d when it is tiny next to c
+fn diffabs<T>(c: T, d: T) -> T
+where
+    T: Copy + Add<Output = T> + Neg<Output = T> + PartialOrd + Default,
+{
+    let zero = T::default();
+    let cd = c + d;
+    if c >= zero {
+        if cd >= zero { d } else { -(c + c + d) }
+    } else if cd > zero {
+        c + c + d
+    } else {
+        -d
+    }
+}
+
+// dz' = f(Z + dz) - f(Z) + dc, written so that nothing big cancels out
+// Works for the f64 and the FloatExp deltas
+fn delta_step<T>(
+    z: Complex<T>,
+    dz: Complex<T>,
+    dc: Complex<T>,
+    formula: FractalFormula,
+) -> Complex<T>
+where
+    T: Copy
+        + Add<Output = T>
+        + Sub<Output = T>
+        + Mul<Output = T>
+        + Neg<Output = T>
+        + PartialOrd
+        + Default,
+{
+    match formula {
+        // dz' = 2*Z*dz + dz² + dc
+        FractalFormula::Multibrot(2) => dz * (z + z + dz) + dc,
+        // (Z+dz)^n - Z^n = dz * sum((Z+dz)^k * Z^(n-1-k)), the sum is built like
+        // s_m = (Z+dz)*s_(m-1) + Z^m
+        FractalFormula::Multibrot(n) => {
+            let z_dz = z + dz;
+            let mut z_m = z;
+            let mut sum = z_dz + z;
+            for _ in 2..n {
+                z_m = z_m * z;
+                sum = sum * z_dz + z_m;
+            }
+            dz * sum + dc
+        }
+        // The sign changes of Complex::fold_square, on the z² delta
+        FractalFormula::BurningShip | FractalFormula::Tricorn | FractalFormula::Celtic => {
+            let w = z * z;
+            let dw = dz * (z + z + dz);
+            let dw = match formula {
+                FractalFormula::BurningShip => Complex::new(dw.re, diffabs(w.im, dw.im)),
+                FractalFormula::Tricorn => Complex::new(dw.re, -dw.im),
+                _ => Complex::new(diffabs(w.re, dw.re), dw.im),
+            };
+            dw + dc
+        }
+    }
+}
+
+// dz_skip ≈ a*dc + b*dc² + c*dc³ for every pixel of the view, so the first
+// `skip` iterations don't have to be looped
+// Only done for z² + c, the coefficients are for that formula
+// The coefficients grow with every skipped iteration, they are kept as
+// FloatExp so they don't overflow at deep zooms
+pub struct SeriesApprox {
+    pub skip: usize,
+    start: usize,
+    a: Complex<FloatExp>,
+    b: Complex<FloatExp>,
+    c: Complex<FloatExp>,
+}
+
+impl Default for SeriesApprox {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl SeriesApprox {
+    // Skipping nothing, dz_1 = dc (or dz_0 = dc for the Julia sets)
+    pub fn new(julia: bool) -> Self {
+        let start = match julia {
+            true => 0,
+            false => 1,
+        };
+        Self {
+            skip: start,
+            start,
+            a: Complex::new(FloatExp::from_f64(1.0), FloatExp::ZERO),
+            b: Complex::new(FloatExp::ZERO, FloatExp::ZERO),
+            c: Complex::new(FloatExp::ZERO, FloatExp::ZERO),
+        }
+    }
+
+    // dc_max is the biggest |dc| of the view, the corners. No pixel can have
+    // escaped during the skipped iterations, their iteration count would be off
+    pub fn compute(
+        orbit: &[Complex<f64>],
+        seq_iter: u32,
+        dc_max: FloatExp,
+        escape_radius: f64,
+        julia: bool,
+    ) -> Self {
+        let mut series = Self::new(julia);
+        // The +dc of every iteration, none for the Julia sets
+        let one = match julia {
+            true => Complex::new(FloatExp::ZERO, FloatExp::ZERO),
+            false => Complex::new(FloatExp::from_f64(1.0), FloatExp::ZERO),
+        };
+        let two = Complex::new(FloatExp::from_f64(2.0), FloatExp::ZERO);
+        let tolerance_sq = FloatExp::from_f64(SERIES_TOLERANCE * SERIES_TOLERANCE);
+
+        // Keeps at least one iteration for the delta loop
+        while series.skip + 2 < orbit.len() && (series.skip as u32) < seq_iter {
+            let z2 = Complex::from_f64_floatexp(orbit[series.skip]) * two;
+            let a = z2 * series.a + one;
+            let b = z2 * series.b + series.a * series.a;
+            let c = z2 * series.c + two * series.a * series.b;
+
+            // |c|*dc_max > tolerance*|b|, squared
+            if c.norm_sqr_floatexp() * dc_max * dc_max > tolerance_sq * b.norm_sqr_floatexp() {
+                break;
+            }
+            // |Z| + |a|*dc_max + |b|*dc_max² + |c|*dc_max³, the furthest pixel
+            let dz_max = ((c.norm_sqr_floatexp().sqrt() * dc_max + b.norm_sqr_floatexp().sqrt())
+                * dc_max
+                + a.norm_sqr_floatexp().sqrt())
+                * dc_max;
+            let z_abs = orbit[series.skip + 1].norm_sqr_f64().sqrt();
+            if (FloatExp::from_f64(z_abs) + dz_max).to_f64() > escape_radius {
+                break;
+            }
+
+            (series.a, series.b, series.c) = (a, b, c);
+            series.skip += 1;
+        }
+        series
+    }
+
+    pub fn skipped_iters(&self) -> u32 {
+        (self.skip - self.start) as u32
+    }
+
+    fn approx(&self, dc: Complex<FloatExp>) -> Complex<FloatExp> {
+        ((self.c * dc + self.b) * dc + self.a) * dc
+    }
+
+    // d(dz_skip)/dc = a + 2*b*dc + 3*c*dc², the dz/dc of the skipped iterations
+    fn approx_derivative(&self, dc: Complex<FloatExp>) -> Complex<FloatExp> {
+        let two = Complex::new(FloatExp::from_f64(2.0), FloatExp::ZERO);
+        let three = Complex::new(FloatExp::from_f64(3.0), FloatExp::ZERO);
+        (three * self.c * dc + two * self.b) * dc + self.a
+    }
+}
+
+impl ReferenceOrbit {
+    pub fn compute(ctx: &FractalContext) -> Self {
+        let center = &ctx.center;
+        let seq_iter = ctx.seq_iter;
+        let zero = rug::Complex::new(center.prec());
+
+        // orbit[m] is Z_m, for the Mandelbrot set Z_0 = 0 so that rebasing can go back to it
+        let formula = ctx.formula;
+        let (orbit, critical_orbit) = match ctx.julia {
+            true => (
+                Self::compute_orbit(center, &ctx.julia_c, ctx),
+                Self::compute_orbit(&zero, &ctx.julia_c, ctx),
+            ),
+            false => (Self::compute_orbit(&zero, center, ctx), Vec::new()),
+        };
+
+        let coloring = Coloring::from_ctx(ctx);
+        let series = match ctx.series_approx
+            && formula == FractalFormula::MANDELBROT
+            && !coloring.needs_every_z()
+        {
+            true => {
+                let dc_max = Complex::from_rug_floatexp(&ctx.window)
+                    .norm_sqr_floatexp()
+                    .sqrt()
+                    * FloatExp::from_f64(0.5);
+                SeriesApprox::compute(&orbit, seq_iter, dc_max, ctx.escape_radius(), ctx.julia)
+            }
+            false => SeriesApprox::new(ctx.julia),
+        };
+
+        Self {
+            orbit,
+            critical_orbit,
+            series,
+            seq_iter,
+            bailout: ctx.bailout(),
+            escape_norm: ctx.escape_norm,
+            julia: ctx.julia,
+            formula,
+            c: match ctx.julia {
+                true => Complex::from_rug_f64(&ctx.julia_c),
+                false => Complex::from_rug_f64(center),
+            },
+            coloring,
+        }
+    }
+
+    fn compute_orbit(
+        z0: &rug::Complex,
+        c: &rug::Complex,
+        ctx: &FractalContext,
+    ) -> Vec<Complex<f64>> {
+        let mut orbit = Vec::with_capacity(ctx.seq_iter as usize + 2);
+        orbit.push(Complex::from_rug_f64(z0));
+
+        let mut scratch = rug::Float::scratch(c.prec().0);
+        let mut z = Complex::<rug::Float>::from_rug(z0);
+        let c = Complex::<rug::Float>::from_rug(c);
+        for _ in 0..=ctx.seq_iter {
+            z.iterate(&c, ctx.formula, &mut scratch);
+            let z_c64 = z.to_f64();
+            orbit.push(z_c64);
+            // Even when the center escapes right away
+            if z_c64.distance_f64(ctx.escape_norm) > ctx.bailout() && orbit.len() >= MIN_ORBIT_LEN {
+                break;
+            }
+        }
+        orbit
+    }
+
+    // Where the deltas go when rebasing, it has to start at 0
+    fn rebase_orbit(&self) -> &[Complex<f64>] {
+        match self.julia {
+            true => &self.critical_orbit,
+            false => &self.orbit,
+        }
+    }
+
+    // Returns the number of iterations done, the distance of the last z and
+    // what was tracked along the orbit for the coloring
+    pub fn iterate_delta(&self, dc: Complex<f64>) -> (u32, f64, OrbitStats) {
+        let dc_fe = Complex::from_f64_floatexp(dc);
+        let dz = self.series.approx(dc_fe);
+        let mut stats = self.start_stats(dz, dc_fe);
+        let dc = match self.julia {
+            true => Complex::new(0.0, 0.0),
+            false => dc,
+        };
+        let (iter, distance) = self.iterate_delta_from(
+            dz.to_f64(),
+            dc,
+            &self.orbit,
+            self.series.skip,
+            self.series.skipped_iters(),
+            &mut stats,
+        );
+        (iter, distance, stats)
+    }
+
+    // Same as `iterate_delta` but for views smaller than what f64 can hold.
+    // The delta stays a FloatExp until it gets big enough for f64, which
+    // doesn't take long since it grows about like |2Z|^n
+    pub fn iterate_delta_floatexp(&self, dc: Complex<FloatExp>) -> (u32, f64, OrbitStats) {
+        let mut dz = self.series.approx(dc);
+        let mut stats = self.start_stats(dz, dc);
+        let dc = match self.julia {
+            true => Complex::new(FloatExp::ZERO, FloatExp::ZERO),
+            false => dc,
+        };
+        let mut reference = &self.orbit[..];
+        let mut m = self.series.skip;
+        let mut distance = 0.0;
+        let mut iter = self.series.skipped_iters();
+        while iter < self.seq_iter && distance <= self.bailout {
+            if dz.re.exp().max(dz.im.exp()) > DELTA_F64_MIN_EXP {
+                let (iter, distance) = self.iterate_delta_from(
+                    dz.to_f64(),
+                    dc.to_f64(),
+                    reference,
+                    m,
+                    iter,
+                    &mut stats,
+                );
+                return (iter, distance, stats);
+            }
+
+            dz = delta_step(
+                Complex::from_f64_floatexp(reference[m]),
+                dz,
+                dc,
+                self.formula,
+            );
+            m += 1;
+            let z = Complex::from_f64_floatexp(reference[m]) + dz;
+            distance = z.distance_floatexp(self.escape_norm);
+            iter += 1;
+            stats.update(z.to_f64());
+
+            if m == reference.len() - 1 || z.norm_sqr_floatexp() < dz.norm_sqr_floatexp() {
+                dz = z;
+                m = 0;
+                reference = self.rebase_orbit();
+            }
+        }
+        (iter, distance, stats)
+    }
+
+    // z and dz/dc where the delta loop starts, right after the skipped iterations
+    fn start_stats(&self, dz: Complex<FloatExp>, dc: Complex<FloatExp>) -> OrbitStats {
+        let c = match self.julia {
+            true => self.c,
+            false => self.c + dc.to_f64(),
+        };
+        OrbitStats::starting_at(
+            self.orbit[self.series.skip] + dz.to_f64(),
+            self.series.approx_derivative(dc),
+            c,
+            &self.coloring,
+        )
+    }
+
+    fn iterate_delta_from<'a>(
+        &'a self,
+        mut dz: Complex<f64>,
+        dc: Complex<f64>,
+        mut reference: &'a [Complex<f64>],
+        mut m: usize,
+        mut iter: u32,
+        stats: &mut OrbitStats,
+    ) -> (u32, f64) {
+        let mut distance = 0.0;
+        while iter < self.seq_iter && distance <= self.bailout {
+            dz = delta_step(reference[m], dz, dc, self.formula);
+            m += 1;
+            let z = reference[m] + dz;
+            distance = z.distance_f64(self.escape_norm);
+            iter += 1;
+            stats.update(z);
+
+            // Rebasing : when the reference runs out or z gets closer to 0 than
+            // to the reference, keep going from the start of the orbit instead
+            if m == reference.len() - 1 || z.norm_sqr_f64() < dz.norm_sqr_f64() {
+                dz = z;
+                m = 0;
+                reference = self.rebase_orbit();
+            }
+        }
+        (iter, distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREC: u32 = 128;
+    const GRID: usize = 24;
+    // The pixels on chaotic orbits go their own way with any precision, f64
+    // and rug disagree there too
+    const MAX_MISMATCHES: usize = GRID * GRID / 100;
+
+    fn ctx_at(center: (f64, f64), window: f64, julia: bool, series_approx: bool) -> FractalContext {
+        let mut ctx = FractalContext {
+            julia,
+            series_approx,
+            seq_iter: 1000,
+            ..Default::default()
+        };
+        ctx.center = rug::Complex::with_val(PREC, center);
+        ctx.window = rug::Complex::with_val(PREC, (window, window));
+        ctx.julia_c.set_prec(PREC);
+        ctx
+    }
+
+    // The pixel iterated in rug, the same loop as the other backends
+    fn iterate_direct(ctx: &FractalContext, dc: Complex<f64>) -> u32 {
+        let mut pixel = ctx.center.clone();
+        pixel += rug::Complex::with_val(PREC, (dc.re, dc.im));
+        let c = match ctx.julia {
+            true => Complex::<rug::Float>::from_rug(&ctx.julia_c),
+            false => Complex::<rug::Float>::from_rug(&pixel),
+        };
+        let mut z = Complex::<rug::Float>::from_rug(&pixel);
+        let mut scratch = rug::Float::scratch(PREC);
+        let (mut iter, mut distance) = (0, 0.0);
+        while iter < ctx.seq_iter && distance <= ctx.bailout() {
+            z.iterate(&c, ctx.formula, &mut scratch);
+            distance = z.to_f64().distance_f64(ctx.escape_norm);
+            iter += 1;
+        }
+        iter
+    }
+
+    // Pixels of the window whose iteration count is off, with the f64 and the
+    // FloatExp deltas
+    fn mismatches(ctx: &FractalContext) -> usize {
+        let reference = ReferenceOrbit::compute(ctx);
+        let window = Complex::from_rug_f64(&ctx.window);
+        let mut mismatches = 0;
+        for y in 0..GRID {
+            for x in 0..GRID {
+                let dc = Complex::new(
+                    (x as f64 / GRID as f64 - 0.5) * window.re,
+                    (y as f64 / GRID as f64 - 0.5) * window.im,
+                );
+                let (iter, _, _) = reference.iterate_delta(dc);
+                let (iter_fe, _, _) =
+                    reference.iterate_delta_floatexp(Complex::from_f64_floatexp(dc));
+                let expected = iterate_direct(ctx, dc);
+                if iter != expected || iter_fe != expected {
+                    mismatches += 1;
+                }
+            }
+        }
+        mismatches
+    }
+
+    #[test]
+    fn shallow_mandelbrot() {
+        for series_approx in [false, true] {
+            assert!(
+                mismatches(&ctx_at((-0.75, 0.1), 0.05, false, series_approx)) <= MAX_MISMATCHES
+            );
+            assert!(mismatches(&ctx_at((-0.1, 0.9), 0.5, false, series_approx)) <= MAX_MISMATCHES);
+        }
+    }
+
+    // Long orbits, the pixels get rebased
+    #[test]
+    fn deep_mandelbrot() {
+        for center in [
+            (-1.7497591451303665, 0.0),
+            (-0.743643887037151, 0.13182590420533),
+        ] {
+            assert!(mismatches(&ctx_at(center, 1e-10, false, true)) <= MAX_MISMATCHES);
+        }
+    }
+
+    // The reference escapes on its first iteration, it is still long enough
+    // for the deltas
+    #[test]
+    fn escaping_center() {
+        for series_approx in [false, true] {
+            for center in [(-1.5, 0.6), (-1.9, 0.15), (3.0, -2.0)] {
+                assert!(mismatches(&ctx_at(center, 0.5, false, series_approx)) <= MAX_MISMATCHES);
+            }
+            assert!(mismatches(&ctx_at((1.5, 1.5), 0.5, true, series_approx)) <= MAX_MISMATCHES);
+        }
+    }
+
+    // The deltas are rebased on the orbit of 0, not on the reference
+    #[test]
+    fn julia() {
+        assert!(mismatches(&ctx_at((0.0, 0.0), 3.0, true, false)) <= MAX_MISMATCHES);
+        assert!(mismatches(&ctx_at((0.1, 0.2), 0.01, true, true)) <= MAX_MISMATCHES);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    fractal_coloring::Coloring,
+    fractal_complex::{Complex, PERIODICITY_MIN_ULPS},
+    fractal_engine::{EscapeNorm, FractalFormula},
+};
+
+// Pixels iterated at once, the 4 f64 of an AVX2 register
+pub const LANES: usize = 4;
+
+// Where the scalar loop of every lane would have stopped
+#[derive(Clone, Copy, Default)]
+pub struct LaneOrbits {
+    pub iter: [u32; LANES],
+    pub distance: [f64; LANES],
+    // Stopped by the periodicity check, the distance is still under the bailout
+    pub periodic: [bool; LANES],
+}
+
+// The F64 escape loop on LANES pixels at once, each lane gets masked out when
+// it escapes or is caught by the periodicity check
+pub struct SimdKernel {
+    pub formula: FractalFormula,
+    pub seq_iter: u32,
+    pub bailout: f64,
+    pub periodicity_epsilon: Option<f64>,
+}
+
+impl SimdKernel {
+    // Only the z² formulas with the euclidean norm are vectorized, and the
+    // colorings that need the orbit keep the scalar loop
+    pub fn supported(
+        formula: FractalFormula,
+        escape_norm: EscapeNorm,
+        coloring: &Coloring,
+    ) -> bool {
+        let quadratic = matches!(
+            formula,
+            FractalFormula::Multibrot(2)
+                | FractalFormula::BurningShip
+                | FractalFormula::Tricorn
+                | FractalFormula::Celtic
+        );
+        quadratic && escape_norm == EscapeNorm::Euclidean && !coloring.tracks_orbit() && avx2()
+    }
+
+    // The skipped lanes are left at 0 iterations, inside the bailout
+    pub fn iterate(
+        &self,
+        z: [Complex<f64>; LANES],
+        c: [Complex<f64>; LANES],
+        skip: [bool; LANES],
+    ) -> LaneOrbits {
+        #[cfg(target_arch = "x86_64")]
+        if avx2() {
+            // SAFETY : AVX2 was just detected
+            return unsafe { self.iterate_avx2(z, c, skip) };
+        }
+        let _ = (z, c, skip);
+        unreachable!("SimdKernel::supported is not used")
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    fn iterate_avx2(
+        &self,
+        z: [Complex<f64>; LANES],
+        c: [Complex<f64>; LANES],
+        skip: [bool; LANES],
+    ) -> LaneOrbits {
+        use std::arch::x86_64::*;
+
+        let set = |f: &dyn Fn(usize) -> f64| _mm256_setr_pd(f(0), f(1), f(2), f(3));
+        let get = |v: __m256d| -> [f64; LANES] { unsafe { std::mem::transmute(v) } };
+
+        // The sign bit cleared for abs() or flipped for the Tricorn
+        let sign = _mm256_set1_pd(-0.0);
+        let zero = _mm256_setzero_pd();
+        let (abs_re, abs_im, neg_im) = match self.formula {
+            FractalFormula::BurningShip => (zero, sign, zero),
+            FractalFormula::Tricorn => (zero, zero, sign),
+            FractalFormula::Celtic => (sign, zero, zero),
+            _ => (zero, zero, zero),
+        };
+
+        let mut re = set(&|i| z[i].re);
+        let mut im = set(&|i| z[i].im);
+        let c_re = set(&|i| c[i].re);
+        let c_im = set(&|i| c[i].im);
+        let bailout = _mm256_set1_pd(self.bailout);
+        let one = _mm256_set1_pd(1.0);
+        let two = _mm256_set1_pd(2.0);
+
+        let mut distance = zero;
+        let mut iter = zero;
+        // All bits set in the lanes that are done
+        let mut done = set(&|i| {
+            if skip[i] {
+                f64::from_bits(u64::MAX)
+            } else {
+                0.0
+            }
+        });
+        let mut periodic = zero;
+
+        // Brent's cycle detection, same as fractal_complex::Periodicity, the
+        // lanes all start together so they share the schedule
+        let epsilon_sq = _mm256_set1_pd(self.periodicity_epsilon.map_or(0.0, |e| e * e));
+        let resolution_sq = _mm256_set1_pd((PERIODICITY_MIN_ULPS * f64::EPSILON).powi(2));
+        let mut saved_re = zero;
+        let mut saved_im = zero;
+        let mut steps = 0;
+        let mut limit = 2;
+
+        for _ in 0..self.seq_iter {
+            let active = _mm256_andnot_pd(done, _mm256_cmp_pd::<_CMP_LE_OQ>(distance, bailout));
+            if _mm256_movemask_pd(active) == 0 {
+                break;
+            }
+
+            let re_sq = _mm256_mul_pd(re, re);
+            let im_sq = _mm256_mul_pd(im, im);
+            let re_im = _mm256_mul_pd(_mm256_mul_pd(re, im), two);
+            let new_re = _mm256_add_pd(_mm256_andnot_pd(abs_re, _mm256_sub_pd(re_sq, im_sq)), c_re);
+            let new_im =
+                _mm256_add_pd(_mm256_xor_pd(_mm256_andnot_pd(abs_im, re_im), neg_im), c_im);
+            re = _mm256_blendv_pd(re, new_re, active);
+            im = _mm256_blendv_pd(im, new_im, active);
+            let new_distance = _mm256_add_pd(_mm256_mul_pd(re, re), _mm256_mul_pd(im, im));
+            distance = _mm256_blendv_pd(distance, new_distance, active);
+            iter = _mm256_add_pd(iter, _mm256_and_pd(active, one));
+
+            if self.periodicity_epsilon.is_some() {
+                let diff_re = _mm256_sub_pd(re, saved_re);
+                let diff_im = _mm256_sub_pd(im, saved_im);
+                let diff = _mm256_add_pd(
+                    _mm256_mul_pd(diff_re, diff_re),
+                    _mm256_mul_pd(diff_im, diff_im),
+                );
+                // The epsilon has to be above the rounding noise of z, distance is |z|²
+                let resolved =
+                    _mm256_cmp_pd::<_CMP_GT_OQ>(epsilon_sq, _mm256_mul_pd(distance, resolution_sq));
+                let caught = _mm256_and_pd(
+                    _mm256_and_pd(active, _mm256_cmp_pd::<_CMP_LE_OQ>(distance, bailout)),
+                    _mm256_and_pd(resolved, _mm256_cmp_pd::<_CMP_LE_OQ>(diff, epsilon_sq)),
+                );
+                periodic = _mm256_or_pd(periodic, caught);
+                done = _mm256_or_pd(done, caught);
+                steps += 1;
+                if steps == limit {
+                    saved_re = re;
+                    saved_im = im;
+                    steps = 0;
+                    limit *= 2;
+                }
+            }
+        }
+
+        let iter = get(iter);
+        let periodic = get(periodic);
+        LaneOrbits {
+            iter: iter.map(|i| i as u32),
+            distance: get(distance),
+            periodic: periodic.map(|p| p.to_bits() != 0),
+        }
+    }
+}
+
+fn avx2() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    return std::arch::is_x86_feature_detected!("avx2");
+    #[cfg(not(target_arch = "x86_64"))]
+    false
+}
@@ -9,10 +9,25 @@ uniform vec4 u_Center;
 uniform vec4 u_Window;
 uniform float u_ConvergeDistance;
 uniform int u_SeqIter;
+uniform bool u_SmoothColoring;
+uniform int u_EscapeNorm; // Same order as fractal_engine::EscapeNorm
+uniform vec2 u_NormWeights;
+uniform bool u_InteriorCheck;
+uniform float u_PeriodicityEpsilon;
+uniform int u_ColoringMode; // Same order as fractal_engine::ColoringMode
+uniform float u_PixelSize;
+uniform bool u_Julia;
+uniform vec4 u_JuliaC;
+uniform int u_Formula; // Same order as fractal_engine::FractalFormula
+uniform int u_Power; // z^n for the Multibrots, 2 for the others
+uniform int u_TrapShape; // Same order as fractal_engine::TrapShape
+uniform vec2 u_TrapCenter;
+uniform float u_TrapRadius;
+uniform float u_TrapAngle;
 
 
-vec3 iter_gradient(int iter) {
-    float t_norm = float(iter) / float(u_SeqIter);
+vec3 iter_gradient(float iter) {
+    float t_norm = iter / float(u_SeqIter);
 
     vec3 red = vec3(1., 0., 0.);
     vec3 green = vec3(0., 1., 0.);
@@ -38,22 +53,94 @@ dvec2 map_pixel_to_value(dvec2 center, dvec2 window, vec2 frag_coord_norm) {
     );
 }
 
-dvec2 sq_add(dvec2 n, dvec2 c) {
-    return dvec2(
-        n.x * n.x - n.y * n.y + c.x,
-        2. * n.x * n.y + c.y
-    );
+dvec2 mul(dvec2 a, dvec2 b) {
+    return dvec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
 }
 
-int compute_number_iter(dvec2 c) {
-    double distance = 0.;
-    dvec2 n = c;
-    int iter = 0;
-    for (; iter < u_SeqIter && distance <= double(u_ConvergeDistance); iter++) {
-        n = sq_add(n, c);
-        distance = abs(n.x) + abs(n.y);
+dvec2 cpow(dvec2 n, int p) {
+    dvec2 r = n;
+    for (int i = 1; i < p; i++) {
+        r = mul(r, n);
+    }
+    return r;
+}
+
+// Same as Complex::f_formula_f64
+dvec2 apply_formula(dvec2 n, dvec2 c) {
+    if (u_Formula == 0) {
+        return cpow(n, u_Power) + c;
+    }
+    dvec2 sq = dvec2(n.x * n.x - n.y * n.y, 2. * n.x * n.y);
+    switch (u_Formula) {
+        case 1: return dvec2(sq.x, abs(sq.y)) + c;
+        case 2: return dvec2(sq.x, -sq.y) + c;
+        default: return dvec2(abs(sq.x), sq.y) + c;
+    }
+}
+
+// Same as fractal_complex::smooth_iter
+float smooth_iter(int iter, double distance, double bailout) {
+    if (bailout <= 1.) {
+        return float(iter);
+    }
+    return max(float(iter) + 1. - log(log(float(distance)) / log(float(bailout))) / log(float(u_Power)), 0.);
+}
+
+double escape_distance(dvec2 n) {
+    switch (u_EscapeNorm) {
+        case 0: return abs(n.x) + abs(n.y);
+        case 1: return dot(n, n);
+        case 2: return max(abs(n.x), abs(n.y));
+        default: return dot(dvec2(u_NormWeights) * n, n);
+    }
+}
+
+// Same as fractal_engine::OrbitTrap::distance
+float trap_distance(dvec2 n) {
+    vec2 d = vec2(n) - u_TrapCenter;
+    float along = abs(d.y * cos(u_TrapAngle) - d.x * sin(u_TrapAngle));
+    float across = abs(d.x * cos(u_TrapAngle) + d.y * sin(u_TrapAngle));
+    switch (u_TrapShape) {
+        case 0: return length(d);
+        case 1: return along;
+        case 2: return min(along, across);
+        default: return abs(length(d) - u_TrapRadius);
     }
-    return iter;
+}
+
+// Same as fractal_coloring::trap_color
+vec3 trap_color(float trap) {
+    return iter_gradient((1. - exp(-trap * 2.)) * float(u_SeqIter));
+}
+
+// Same as fractal_coloring::OrbitStats::average_term, -1 when there is none
+// z_1 and z_2 are the two z before z
+float average_term(vec2 z, vec2 z_1, vec2 z_2, vec2 c) {
+    if (u_ColoringMode == 4) {
+        return 0.5 * sin(5. * atan(z.y, z.x)) + 0.5;
+    } else if (u_ColoringMode == 5) {
+        float z_pow = pow(length(z_1), float(u_Power));
+        float c_abs = length(c);
+        float low = abs(z_pow - c_abs);
+        float high = z_pow + c_abs;
+        return high - low > 0. ? (length(z) - low) / (high - low) : -1.;
+    }
+    vec2 a = z - z_1;
+    vec2 b = z_1 - z_2;
+    if (dot(b, b) == 0.) {
+        return -1.;
+    }
+    // a * conj(b) has the same argument as a / b
+    vec2 turn = vec2(a.x * b.x + a.y * b.y, a.y * b.x - a.x * b.y);
+    return dot(turn, turn) == 0. ? 0. : abs(atan(turn.y, turn.x)) / 3.14159265;
+}
+
+// Main cardioid and period 2 bulb
+bool in_cardioid_or_bulb(dvec2 c) {
+    double x = c.x - 0.25;
+    double y_sq = c.y * c.y;
+    double q = x * x + y_sq;
+    return q * (q + x) <= 0.25 * y_sq || (c.x + 1.) * (c.x + 1.) + y_sq <= 0.0625;
 }
 
 dvec2 vec4_to_dvec2(vec4 data) {
@@ -64,6 +151,83 @@ dvec2 vec4_to_dvec2(vec4 data) {
     );
 }
 
+// Returns -1 when the pixel doesn't escape, de is the distance estimate to the boundary
+// trap the closest the orbit got to the orbit trap and avg the orbit average
+// The Julia sets start at z = pixel with the fixed c, the Mandelbrot set at z = c = pixel
+float compute_number_iter(dvec2 pixel, out float de, out float trap, out float avg) {
+    de = 0.;
+    trap = trap_distance(pixel);
+    avg = 0.;
+    dvec2 c = u_Julia ? vec4_to_dvec2(u_JuliaC) : pixel;
+    if (u_InteriorCheck && !u_Julia && u_Formula == 0 && u_Power == 2 && in_cardioid_or_bulb(c)) {
+        return -1.;
+    }
+    // The squared norms are compared to the squared converge distance
+    double bailout = (u_EscapeNorm == 1 || u_EscapeNorm == 3)
+        ? double(u_ConvergeDistance) * double(u_ConvergeDistance)
+        : double(u_ConvergeDistance);
+    double distance = 0.;
+    dvec2 n = pixel;
+    dvec2 dn = dvec2(1., 0.); // dz/dc, or dz/dz_0 for the Julia sets
+    // Brent's cycle detection, same as fractal_complex::Periodicity
+    double epsilon_sq = double(u_PeriodicityEpsilon) * double(u_PeriodicityEpsilon);
+    // 16 ulps, fractal_complex::PERIODICITY_MIN_ULPS
+    double resolution_sq = 3.552713678800501e-15lf * 3.552713678800501e-15lf;
+    dvec2 saved = dvec2(0.);
+    int steps = 0;
+    int limit = 2;
+    // Same as the averages of fractal_coloring::OrbitStats
+    float avg_sum = 0.;
+    float avg_last = 0.;
+    int avg_count = 0;
+    dvec2 n_prev = n;
+    int iter = 0;
+    for (; iter < u_SeqIter && distance <= bailout; iter++) {
+        if (u_ColoringMode == 1 || u_ColoringMode == 2) {
+            dn = double(u_Power) * mul(cpow(n, u_Power - 1), dn) + (u_Julia ? dvec2(0.) : dvec2(1., 0.));
+        }
+        dvec2 n_old = n;
+        n = apply_formula(n, c);
+        distance = escape_distance(n);
+        if (u_ColoringMode == 3) {
+            trap = min(trap, trap_distance(n));
+        }
+        if (u_ColoringMode >= 4) {
+            float t = average_term(vec2(n), vec2(n_old), vec2(n_prev), vec2(c));
+            if (t >= 0.) {
+                avg_sum += t;
+                avg_last = t;
+                avg_count++;
+            }
+            n_prev = n_old;
+        }
+        if (u_InteriorCheck && distance <= bailout) {
+            dvec2 diff = n - saved;
+            if (epsilon_sq > dot(n, n) * resolution_sq && dot(diff, diff) <= epsilon_sq) {
+                return -1.;
+            }
+            steps++;
+            if (steps == limit) {
+                saved = n;
+                steps = 0;
+                limit *= 2;
+            }
+        }
+    }
+    if (distance <= bailout) {
+        return -1.;
+    }
+    float n_abs = float(length(n));
+    de = n_abs * log(n_abs) / float(length(dn));
+    float iter_smooth = smooth_iter(iter, distance, bailout);
+    if (avg_count >= 2) {
+        float cur = avg_sum / float(avg_count);
+        float prev = (avg_sum - avg_last) / float(avg_count - 1);
+        avg = clamp(mix(prev, cur, fract(iter_smooth)), 0., 1.);
+    }
+    return u_SmoothColoring ? iter_smooth : float(iter);
+}
+
 void main()
 {
     vec2 frag_coord_norm = gl_FragCoord.xy/u_Resolution;
@@ -72,10 +236,24 @@ void main()
     dvec2 window = vec4_to_dvec2(u_Window);
 
     dvec2 value = map_pixel_to_value(center, window, frag_coord_norm);
-    int iter = compute_number_iter(value);
+    float de;
+    float trap;
+    float avg;
+    float iter = compute_number_iter(value, de, trap, avg);
 
-    if (iter == u_SeqIter) {
+    if (u_ColoringMode == 3) {
+        // Same as fractal_coloring::Coloring::interior, dimmer inside the set
+        gl_FragColor = vec4(trap_color(trap) * (iter < 0. ? 0.5 : 1.), 1.);
+    } else if (iter < 0.) {
         gl_FragColor = vec4(vec3(0.), 1.);
+    } else if (u_ColoringMode == 1) {
+        // Same as fractal_coloring::Coloring::escaped
+        gl_FragColor = vec4(vec3(clamp(de / u_PixelSize, 0., 1.)), 1.);
+    } else if (u_ColoringMode == 2) {
+        float shade = sqrt(clamp(de / u_PixelSize / 4., 0., 1.));
+        gl_FragColor = vec4(iter_gradient(iter) * shade, 1.);
+    } else if (u_ColoringMode >= 4) {
+        gl_FragColor = vec4(iter_gradient(avg * float(u_SeqIter)), 1.);
     } else {
         vec3 color = iter_gradient(iter);
         gl_FragColor = vec4(color, 1.);
@@ -1,5 +1,16 @@
+mod fractal_buddhabrot;
+mod fractal_coloring;
 mod fractal_complex;
+mod fractal_double_double;
 mod fractal_engine;
+mod fractal_expr;
+mod fractal_finder;
+mod fractal_fixed;
+mod fractal_floatexp;
+mod fractal_lyapunov;
+mod fractal_newton;
+mod fractal_perturbation;
+mod fractal_simd;
 mod gui_wrapper;
 
 mod sfml_engine;
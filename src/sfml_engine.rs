@@ -16,9 +16,10 @@ use rug::{
 use crate::{
     fractal_complex::Complex,
     fractal_engine::{
-        self, BASE_CENTER, BASE_WINDOW, FRCTL_CTX_CMPLX_PREC, FractalBackend, FractalContext,
-        FractalEngine, FractalNotif, lodiv, seq_iters_formula,
+        self, ColoringMode, EscapeNorm, FractalBackend, FractalContext, FractalEngine,
+        FractalFormula, FractalMode, FractalNotif, MIN_RUG_PREC, OrbitTrap, TrapShape, lodiv,
     },
+    fractal_expr::{self, StartZ},
     sfml_engine_internal::SfmlEngineInternal,
 };
 
@@ -48,10 +49,112 @@ impl SfmlEngine {
         }
     }
 
+    // From the highest degree to the constant
+    fn gui_newton_coeffs(&mut self, ui: &mut Ui, coeffs: &mut Vec<Complex<f64>>) {
+        let mut changed = false;
+        let mut drag_stopped = false;
+
+        ui.horizontal(|ui| {
+            ui.label("Degree : ");
+            if ui.button(" - ").clicked() && coeffs.len() > 3 {
+                coeffs.pop();
+                changed = true;
+                drag_stopped = true;
+            }
+            ui.label((coeffs.len() - 1).to_string());
+            if ui.button(" + ").clicked() {
+                coeffs.push(Complex::new(1.0, 0.0));
+                changed = true;
+                drag_stopped = true;
+            }
+        });
+
+        for (k, coeff) in coeffs.iter_mut().enumerate().rev() {
+            ui.horizontal(|ui| {
+                ui.label(format!("z^{k} : "));
+                let re_drag_value = ui.add(egui::DragValue::new(&mut coeff.re).speed(0.01));
+                let im_drag_value =
+                    ui.add(egui::DragValue::new(&mut coeff.im).speed(0.01).suffix("i"));
+                changed |= re_drag_value.changed() || im_drag_value.changed();
+                drag_stopped |= re_drag_value.drag_stopped() || im_drag_value.drag_stopped();
+            });
+        }
+
+        if changed {
+            self.set_newton_coeffs(coeffs.clone());
+        }
+        if drag_stopped {
+            self.reload();
+        }
+    }
+
+    fn gui_orbit_trap(&mut self, ui: &mut Ui, trap: &mut OrbitTrap) {
+        let mut changed = false;
+        let mut drag_stopped = false;
+
+        ui.horizontal(|ui| {
+            ui.label("Trap : ");
+            for (shape, name) in [
+                (TrapShape::Point, "Point"),
+                (TrapShape::Line, "Line"),
+                (TrapShape::Cross, "Cross"),
+                (TrapShape::Circle, "Circle"),
+            ] {
+                if ui.selectable_value(&mut trap.shape, shape, name).clicked() {
+                    changed = true;
+                    drag_stopped = true;
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Trap Center : ");
+            let re_drag_value = ui.add(egui::DragValue::new(&mut trap.center.re).speed(0.01));
+            let im_drag_value = ui.add(
+                egui::DragValue::new(&mut trap.center.im)
+                    .speed(0.01)
+                    .suffix("i"),
+            );
+            changed |= re_drag_value.changed() || im_drag_value.changed();
+            drag_stopped |= re_drag_value.drag_stopped() || im_drag_value.drag_stopped();
+        });
+
+        match trap.shape {
+            TrapShape::Circle => {
+                ui.horizontal(|ui| {
+                    ui.label("Trap Radius : ");
+                    let drag_value = ui.add(
+                        egui::DragValue::new(&mut trap.radius)
+                            .speed(0.01)
+                            .range(0.0..=f64::MAX),
+                    );
+                    changed |= drag_value.changed();
+                    drag_stopped |= drag_value.drag_stopped();
+                });
+            }
+            TrapShape::Line | TrapShape::Cross => {
+                ui.horizontal(|ui| {
+                    ui.label("Trap Angle : ");
+                    let mut angle = trap.angle as f32;
+                    let drag_value = ui.drag_angle(&mut angle);
+                    trap.angle = angle as f64;
+                    changed |= drag_value.changed();
+                    drag_stopped |= drag_value.drag_stopped();
+                });
+            }
+            TrapShape::Point => {}
+        }
+
+        if changed {
+            self.set_orbit_trap(*trap);
+        }
+        if drag_stopped {
+            self.reload();
+        }
+    }
+
     fn set_rug_prec(&mut self, prec: u32) {
-        let mut ctx = self.ctx_rwl.write().unwrap();
-        ctx.window.set_prec(prec);
-        ctx.center.set_prec(prec);
+        self.ctx_rwl.write().unwrap().set_prec(prec);
     }
 }
 
@@ -71,21 +174,13 @@ impl FractalEngine for SfmlEngine {
     }
 
     fn reset_window(&mut self) {
-        let mut ctx = self.ctx_rwl.write().unwrap();
-        ctx.window = rug::Complex::with_val(FRCTL_CTX_CMPLX_PREC, BASE_WINDOW);
-        let mut new_real = ctx.window.real().clone();
-        new_real.mul_from(ctx.res.y as f32 / ctx.res.x as f32);
-        ctx.window.mut_imag().assign(new_real);
-        if ctx.auto_seq_iter {
-            ctx.seq_iter = seq_iters_formula(&ctx.window, ctx.auto_seq_iter_fact);
-        }
-        drop(ctx);
+        self.ctx_rwl.write().unwrap().reset_window();
     }
 
     fn reset_view(&mut self) {
         self.reset_window();
-        self.ctx_rwl.write().unwrap().center =
-            rug::Complex::with_val(FRCTL_CTX_CMPLX_PREC, BASE_CENTER);
+        let mut ctx = self.ctx_rwl.write().unwrap();
+        ctx.center = ctx.base_center();
     }
 
     fn reload(&mut self) {
@@ -103,6 +198,7 @@ impl FractalEngine for SfmlEngine {
         imag_offset.mul_from(0.5 * trsln.im); // why minus ??
         ctx.center.add_from(real_offset);
         ctx.center.mut_imag().add_from(imag_offset);
+        ctx.adjust_auto_prec();
 
         drop(ctx);
         self.reload()
@@ -114,6 +210,7 @@ impl FractalEngine for SfmlEngine {
         if ctx.auto_seq_iter {
             ctx.seq_iter = fractal_engine::seq_iters_formula(&ctx.window, ctx.auto_seq_iter_fact);
         }
+        ctx.adjust_auto_prec();
         drop(ctx);
         self.reload()
     }
@@ -148,6 +245,22 @@ impl FractalEngine for SfmlEngine {
         self.ctx_rwl.write().unwrap().auto_seq_iter_fact = auto_seq_iter_fact;
     }
 
+    fn set_auto_prec(&mut self, auto_prec: bool) {
+        let mut ctx = self.ctx_rwl.write().unwrap();
+        ctx.auto_prec = auto_prec;
+        ctx.adjust_auto_prec();
+    }
+
+    fn set_prec_margin(&mut self, prec_margin: u32) {
+        let mut ctx = self.ctx_rwl.write().unwrap();
+        ctx.prec_margin = prec_margin;
+        ctx.adjust_auto_prec();
+    }
+
+    fn set_fixed_point(&mut self, fixed_point: bool) {
+        self.ctx_rwl.write().unwrap().fixed_point = fixed_point;
+    }
+
     fn set_workers(&mut self, workers: usize) {
         self.ctx_rwl.write().unwrap().worker_count = workers;
     }
@@ -156,6 +269,91 @@ impl FractalEngine for SfmlEngine {
         self.ctx_rwl.write().unwrap().converge_distance = converge_distance;
     }
 
+    fn set_series_approx(&mut self, series_approx: bool) {
+        self.ctx_rwl.write().unwrap().series_approx = series_approx;
+    }
+
+    fn set_smooth_coloring(&mut self, smooth_coloring: bool) {
+        self.ctx_rwl.write().unwrap().smooth_coloring = smooth_coloring;
+    }
+
+    fn set_escape_norm(&mut self, escape_norm: EscapeNorm) {
+        self.ctx_rwl.write().unwrap().escape_norm = escape_norm;
+    }
+
+    fn set_interior_check(&mut self, interior_check: bool) {
+        self.ctx_rwl.write().unwrap().interior_check = interior_check;
+    }
+
+    fn set_coloring_mode(&mut self, coloring_mode: ColoringMode) {
+        self.ctx_rwl.write().unwrap().coloring_mode = coloring_mode;
+    }
+
+    fn set_julia(&mut self, julia: bool) {
+        self.ctx_rwl.write().unwrap().julia = julia;
+        // Both don't live at the same place
+        self.reset_view();
+    }
+
+    fn set_julia_c(&mut self, julia_c: Complex<f64>) {
+        self.ctx_rwl
+            .write()
+            .unwrap()
+            .julia_c
+            .assign((julia_c.re, julia_c.im));
+    }
+
+    fn set_formula(&mut self, formula: FractalFormula) {
+        self.ctx_rwl.write().unwrap().formula = formula;
+    }
+
+    fn set_mode(&mut self, mode: FractalMode) {
+        self.ctx_rwl.write().unwrap().mode = mode;
+        // Both don't live at the same place
+        self.reset_view();
+    }
+
+    fn set_newton_coeffs(&mut self, newton_coeffs: Vec<Complex<f64>>) {
+        self.ctx_rwl.write().unwrap().newton_coeffs = newton_coeffs;
+    }
+
+    fn set_buddha_iters(&mut self, buddha_iters: [u32; 3]) {
+        self.ctx_rwl.write().unwrap().buddha_iters = buddha_iters;
+    }
+
+    fn set_buddha_samples(&mut self, buddha_samples: u32) {
+        self.ctx_rwl.write().unwrap().buddha_samples = buddha_samples;
+    }
+
+    fn set_buddha_max_passes(&mut self, buddha_max_passes: u32) {
+        self.ctx_rwl.write().unwrap().buddha_max_passes = buddha_max_passes;
+    }
+
+    fn set_lyapunov_seq(&mut self, lyapunov_seq: String) {
+        self.ctx_rwl.write().unwrap().lyapunov_seq = lyapunov_seq;
+    }
+
+    // The last formula that parsed keeps being drawn until the new one does
+    fn set_custom_formula(&mut self, custom_formula: String) {
+        let mut ctx = self.ctx_rwl.write().unwrap();
+        if let Ok(expr) = fractal_expr::parse(&custom_formula) {
+            ctx.custom_expr = Arc::new(expr);
+        }
+        ctx.custom_formula = custom_formula;
+    }
+
+    fn set_custom_start(&mut self, custom_start: StartZ) {
+        self.ctx_rwl.write().unwrap().custom_start = custom_start;
+    }
+
+    fn set_custom_bailout(&mut self, custom_bailout: f64) {
+        self.ctx_rwl.write().unwrap().custom_bailout = custom_bailout;
+    }
+
+    fn set_orbit_trap(&mut self, orbit_trap: OrbitTrap) {
+        self.ctx_rwl.write().unwrap().orbit_trap = orbit_trap;
+    }
+
     fn gui_central_panel(&mut self, ui: &mut Ui) {
         let mut ctx;
         {
@@ -175,6 +373,22 @@ impl FractalEngine for SfmlEngine {
 
         ui.add_space(7.0);
 
+        if ui
+            .radio_value(
+                &mut ctx.backend,
+                FractalBackend::F32,
+                "32-bit floating point (preview)",
+            )
+            .clicked()
+        {
+            self.set_backend(FractalBackend::F32);
+        }
+        if ctx.backend == FractalBackend::F32
+            && ctx.mode == FractalMode::Escape
+            && !ctx.f32_is_enough()
+        {
+            ui.label("(Too deep for f32, running on F64)");
+        }
         if ui
             .radio_value(
                 &mut ctx.backend,
@@ -185,6 +399,16 @@ impl FractalEngine for SfmlEngine {
         {
             self.set_backend(FractalBackend::F64);
         }
+        if ui
+            .radio_value(
+                &mut ctx.backend,
+                FractalBackend::DoubleDouble,
+                "128-bit double-double",
+            )
+            .clicked()
+        {
+            self.set_backend(FractalBackend::DoubleDouble);
+        }
         if ui
             .radio_value(
                 &mut ctx.backend,
@@ -195,6 +419,16 @@ impl FractalEngine for SfmlEngine {
         {
             self.set_backend(FractalBackend::Rug);
         }
+        if ui
+            .radio_value(
+                &mut ctx.backend,
+                FractalBackend::Perturbation,
+                "Perturbation (deep zoom)",
+            )
+            .clicked()
+        {
+            self.set_backend(FractalBackend::Perturbation);
+        }
 
         if ui
             .radio_value(&mut ctx.backend, FractalBackend::Shader, "GPU Shaders")
@@ -207,15 +441,66 @@ impl FractalEngine for SfmlEngine {
 
         ui.horizontal(|ui| {
             ui.label("Precision : ");
-            let drag_value = ui.add(egui::DragValue::new(&mut rug_prec).range(64..=u32::MAX));
+            let drag_value = ui.add_enabled(
+                !ctx.auto_prec,
+                egui::DragValue::new(&mut rug_prec).range(MIN_RUG_PREC..=u32::MAX),
+            );
             if drag_value.changed() {
                 self.set_rug_prec(rug_prec);
             }
             if drag_value.drag_stopped() {
                 self.reload();
             }
+
+            if ui.checkbox(&mut ctx.auto_prec, "Auto").clicked() {
+                self.set_auto_prec(ctx.auto_prec);
+                self.reload();
+            }
+            let margin_drag_value = ui.add_enabled(
+                ctx.auto_prec,
+                egui::DragValue::new(&mut ctx.prec_margin).prefix("+"),
+            );
+            if margin_drag_value.changed() {
+                self.set_prec_margin(ctx.prec_margin);
+            }
+            // Typed in, it never gets dragged
+            if margin_drag_value.drag_stopped() || margin_drag_value.lost_focus() {
+                self.reload();
+            }
         });
 
+        let required_prec = ctx.required_prec();
+        if rug_prec < required_prec
+            && matches!(
+                ctx.backend,
+                FractalBackend::Rug | FractalBackend::Perturbation
+            )
+        {
+            ui.label(
+                RichText::new(format!(
+                    "Precision too low for this zoom, the pixels will repeat ({required_prec} bits needed)"
+                ))
+                .color(egui::Color32::ORANGE),
+            );
+        }
+
+        if ctx.backend == FractalBackend::Rug && ctx.mode == FractalMode::Escape {
+            ui.horizontal(|ui| {
+                if ui
+                    .checkbox(&mut ctx.fixed_point, "Fixed-point instead of MPFR")
+                    .clicked()
+                {
+                    self.set_fixed_point(ctx.fixed_point);
+                    self.reload();
+                }
+                match ctx.fixed_point_limbs() {
+                    Some(limbs) => ui.label(format!("({} bits)", limbs * 64)),
+                    None if ctx.fixed_point => ui.label("(Out of its range, running on MPFR)"),
+                    None => ui.label(""),
+                };
+            });
+        }
+
         ui.horizontal(|ui| {
             ui.label("Sequence Iterations : ");
 
@@ -262,6 +547,292 @@ impl FractalEngine for SfmlEngine {
             }
         });
 
+        if ui
+            .add_enabled(
+                ctx.backend == FractalBackend::Perturbation,
+                egui::Checkbox::new(&mut ctx.series_approx, "Series Approximation"),
+            )
+            .clicked()
+        {
+            self.set_series_approx(ctx.series_approx);
+            self.reload();
+        }
+
+        // The custom formulas have no power to smooth the iterations with
+        let has_power = ctx.power().is_some();
+        if ui
+            .add_enabled(
+                has_power,
+                egui::Checkbox::new(&mut ctx.smooth_coloring, "Smooth Coloring"),
+            )
+            .clicked()
+        {
+            self.set_smooth_coloring(ctx.smooth_coloring);
+            self.reload();
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Coloring : ");
+            for (coloring_mode, name) in [
+                (ColoringMode::Iteration, "Iterations"),
+                (ColoringMode::DistanceBoundary, "Boundary"),
+                (ColoringMode::DistanceDarkened, "Distance Darkened"),
+                (ColoringMode::OrbitTrap, "Orbit Trap"),
+                (ColoringMode::StripeAverage, "Stripes"),
+                (ColoringMode::TriangleAverage, "Triangle Inequality"),
+                (ColoringMode::CurvatureAverage, "Curvature"),
+            ] {
+                if ui
+                    .add_enabled(
+                        has_power || !coloring_mode.needs_power(),
+                        egui::SelectableLabel::new(ctx.coloring_mode == coloring_mode, name),
+                    )
+                    .clicked()
+                {
+                    self.set_coloring_mode(coloring_mode);
+                    self.reload();
+                }
+            }
+        });
+
+        if ctx.coloring_mode == ColoringMode::OrbitTrap {
+            self.gui_orbit_trap(ui, &mut ctx.orbit_trap);
+        }
+
+        if ui
+            .checkbox(&mut ctx.interior_check, "Interior Detection")
+            .clicked()
+        {
+            self.set_interior_check(ctx.interior_check);
+            self.reload();
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Mode : ");
+            for (mode, name) in [
+                (FractalMode::Escape, "Escape Time"),
+                (FractalMode::Newton, "Newton"),
+                (FractalMode::Buddhabrot, "Buddhabrot"),
+                (FractalMode::Lyapunov, "Lyapunov"),
+                (FractalMode::Custom, "Custom"),
+            ] {
+                if ui.selectable_value(&mut ctx.mode, mode, name).clicked() {
+                    self.set_mode(mode);
+                    self.reload();
+                }
+            }
+        });
+
+        if ctx.mode == FractalMode::Newton {
+            self.gui_newton_coeffs(ui, &mut ctx.newton_coeffs);
+            ui.label("(Newton runs on the F64 or Rug backend)");
+        }
+
+        if ctx.mode == FractalMode::Buddhabrot {
+            ui.horizontal(|ui| {
+                ui.label("Iterations : ");
+                let mut drag_stopped = false;
+                for (id, channel) in ["R: ", "G: ", "B: "].into_iter().enumerate() {
+                    let drag_value = ui.add(
+                        egui::DragValue::new(&mut ctx.buddha_iters[id])
+                            .range(1..=u32::MAX)
+                            .prefix(channel),
+                    );
+                    if drag_value.changed() {
+                        self.set_buddha_iters(ctx.buddha_iters);
+                    }
+                    drag_stopped |= drag_value.drag_stopped();
+                }
+                if drag_stopped {
+                    self.reload();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Samples per Pass : ");
+                let drag_value = ui.add(
+                    egui::DragValue::new(&mut ctx.buddha_samples)
+                        .range(1000..=u32::MAX)
+                        .speed(1000),
+                );
+                if drag_value.changed() {
+                    self.set_buddha_samples(ctx.buddha_samples);
+                }
+                if drag_value.drag_stopped() {
+                    self.reload();
+                }
+
+                ui.label("Passes : ");
+                let drag_value =
+                    ui.add(egui::DragValue::new(&mut ctx.buddha_max_passes).range(1..=u32::MAX));
+                if drag_value.changed() {
+                    self.set_buddha_max_passes(ctx.buddha_max_passes);
+                }
+            });
+            ui.label("(The Buddhabrot runs on the F64 backend)");
+        }
+
+        if ctx.mode == FractalMode::Lyapunov {
+            ui.horizontal(|ui| {
+                ui.label("Sequence : ");
+                let text_edit = ui.text_edit_singleline(&mut ctx.lyapunov_seq);
+                if text_edit.changed() {
+                    ctx.lyapunov_seq
+                        .retain(|c| matches!(c.to_ascii_uppercase(), 'A' | 'B'));
+                    self.set_lyapunov_seq(ctx.lyapunov_seq.to_ascii_uppercase());
+                }
+                if text_edit.lost_focus() {
+                    self.reload();
+                }
+            });
+            ui.label("(Lyapunov runs on the F64 backend, x is a and y is b)");
+        }
+
+        if ctx.mode == FractalMode::Custom {
+            ui.horizontal(|ui| {
+                ui.label("z = ");
+                let text_edit = ui.text_edit_singleline(&mut ctx.custom_formula);
+                if text_edit.changed() {
+                    self.set_custom_formula(ctx.custom_formula.clone());
+                }
+                if text_edit.lost_focus() {
+                    self.reload();
+                }
+            });
+            if let Err(err) = fractal_expr::parse(&ctx.custom_formula) {
+                ui.label(
+                    RichText::new(format!("{err}, the last valid formula is drawn"))
+                        .color(egui::Color32::ORANGE),
+                );
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Start : ");
+                for (custom_start, name) in [(StartZ::Zero, "z = 0"), (StartZ::Pixel, "z = c")] {
+                    if ui
+                        .selectable_value(&mut ctx.custom_start, custom_start, name)
+                        .clicked()
+                    {
+                        self.set_custom_start(custom_start);
+                        self.reload();
+                    }
+                }
+
+                ui.label("Bailout : ");
+                let drag_value = ui.add(
+                    egui::DragValue::new(&mut ctx.custom_bailout)
+                        .range(0.0..=f64::MAX)
+                        .speed(0.1),
+                );
+                if drag_value.changed() {
+                    self.set_custom_bailout(ctx.custom_bailout);
+                }
+                if drag_value.drag_stopped() {
+                    self.reload();
+                }
+            });
+            ui.label("(sin cos tan sinh cosh tanh exp ln sqrt recip conj abs arg)");
+            ui.label("(Custom formulas run on the F64 or Rug backend)");
+        }
+
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut ctx.julia, "Julia").clicked() {
+                self.set_julia(ctx.julia);
+                self.reload();
+            }
+            let mut julia_c = Complex::from_rug_f64(&ctx.julia_c);
+            let re_drag_value = ui.add_enabled(
+                ctx.julia,
+                egui::DragValue::new(&mut julia_c.re)
+                    .speed(0.001)
+                    .prefix("c re: "),
+            );
+            let im_drag_value = ui.add_enabled(
+                ctx.julia,
+                egui::DragValue::new(&mut julia_c.im)
+                    .speed(0.001)
+                    .prefix("c im: "),
+            );
+            if re_drag_value.changed() || im_drag_value.changed() {
+                self.set_julia_c(julia_c);
+            }
+            if re_drag_value.drag_stopped() || im_drag_value.drag_stopped() {
+                self.reload();
+            }
+        });
+        ui.label("(Ctrl + Click on the fractal to pick c)");
+
+        ui.horizontal(|ui| {
+            ui.label("Formula : ");
+            let power = match ctx.formula {
+                FractalFormula::Multibrot(n) => n,
+                _ => 2,
+            };
+            for (formula, name) in [
+                (FractalFormula::Multibrot(power), "Multibrot"),
+                (FractalFormula::BurningShip, "Burning Ship"),
+                (FractalFormula::Tricorn, "Tricorn"),
+                (FractalFormula::Celtic, "Celtic"),
+            ] {
+                let selected =
+                    std::mem::discriminant(&ctx.formula) == std::mem::discriminant(&formula);
+                if ui.selectable_label(selected, name).clicked() {
+                    self.set_formula(formula);
+                    self.reload();
+                }
+            }
+            if let FractalFormula::Multibrot(mut power) = ctx.formula {
+                let drag_value = ui.add(egui::DragValue::new(&mut power).range(2..=8).prefix("z^"));
+                if drag_value.changed() {
+                    self.set_formula(FractalFormula::Multibrot(power));
+                }
+                if drag_value.drag_stopped() {
+                    self.reload();
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Escape Norm : ");
+            let (w_re, w_im) = match ctx.escape_norm {
+                EscapeNorm::Weighted(w_re, w_im) => (w_re, w_im),
+                _ => (1.0, 1.0),
+            };
+            for (escape_norm, name) in [
+                (EscapeNorm::L1, "L1"),
+                (EscapeNorm::Euclidean, "Euclidean"),
+                (EscapeNorm::LInf, "L∞"),
+                (EscapeNorm::Weighted(w_re, w_im), "Weighted"),
+            ] {
+                let selected = std::mem::discriminant(&ctx.escape_norm)
+                    == std::mem::discriminant(&escape_norm);
+                if ui.selectable_label(selected, name).clicked() {
+                    self.set_escape_norm(escape_norm);
+                    self.reload();
+                }
+            }
+            if let EscapeNorm::Weighted(mut w_re, mut w_im) = ctx.escape_norm {
+                let re_drag_value = ui.add(
+                    egui::DragValue::new(&mut w_re)
+                        .range(0.01..=100.0)
+                        .speed(0.01)
+                        .prefix("re: "),
+                );
+                let im_drag_value = ui.add(
+                    egui::DragValue::new(&mut w_im)
+                        .range(0.01..=100.0)
+                        .speed(0.01)
+                        .prefix("im: "),
+                );
+                if re_drag_value.changed() || im_drag_value.changed() {
+                    self.set_escape_norm(EscapeNorm::Weighted(w_re, w_im));
+                }
+                if re_drag_value.drag_stopped() || im_drag_value.drag_stopped() {
+                    self.reload();
+                }
+            }
+        });
+
         ui.horizontal(|ui| {
             ui.label("Workers : ");
             if ui.button(" - ").clicked() && ctx.worker_count >= 1 {
@@ -383,10 +954,54 @@ impl FractalEngine for SfmlEngine {
                 ui.horizontal(|ui| {
                     ui.label(RichText::new(format!("Worker {} :", id)).strong());
                     ui.label(format!("{:?}", dur));
+                    ui.label(format!(
+                        "({} interior pixels skipped)",
+                        ctx.short_circuited[id]
+                    ));
                 });
             }
         });
 
+        if ctx.skips_interior() && ctx.backend != FractalBackend::Shader {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Interior Pixels Skipped :").strong());
+                ui.label(format!("{}", ctx.short_circuited.iter().sum::<u32>()));
+            });
+        }
+
+        if ctx.mode == FractalMode::Buddhabrot {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Buddhabrot Passes :").strong());
+                ui.label(format!("{}/{}", ctx.buddha_passes, ctx.buddha_max_passes));
+            });
+        }
+
+        if let Some(period) = ctx.minibrot_period {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Minibrot Period :").strong());
+                ui.label(format!("{period}"));
+            });
+        }
+
+        if let Some(misiurewicz) = &ctx.misiurewicz {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Misiurewicz Point :").strong());
+                ui.label(format!(
+                    "preperiod {}, period {}",
+                    misiurewicz.preperiod, misiurewicz.period
+                ));
+            });
+            ui.label(format!("re : {}", misiurewicz.point.real()));
+            ui.label(format!("im : {}", misiurewicz.point.imag()));
+        }
+
+        if ctx.backend == FractalBackend::Perturbation {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Skipped Iterations :").strong());
+                ui.label(format!("{}", ctx.skipped_iters));
+            });
+        }
+
         ui.horizontal(|ui| {
             ui.label(RichText::new("Resolution :").strong());
             ui.label(format!(
@@ -15,15 +15,26 @@ use sfml::{
         Sprite, Texture, Transformable, View,
     },
     system::Vector2f,
-    window::{ContextSettings, Event, Style, mouse::Button},
+    window::{ContextSettings, Event, Key, Style, mouse::Button},
 };
 
 use crate::{
-    fractal_complex,
-    fractal_engine::{self, FractalBackend, FractalContext, FractalNotif},
+    fractal_buddhabrot, fractal_complex,
+    fractal_engine::{
+        self, BASE_WINDOW, ColoringMode, EscapeNorm, FractalBackend, FractalContext,
+        FractalFormula, FractalMode, FractalNotif, TrapShape,
+    },
+    fractal_finder::{Minibrot, Misiurewicz},
+    fractal_perturbation::ReferenceOrbit,
     sfml_engine_worker::SfmlEngineWorkerInternal,
 };
 
+// The minibrots and the Misiurewicz points are searched for in that fraction
+// of the view around the mouse
+const FINDER_SEARCH_FACT: f64 = 0.05;
+// How much bigger than the minibrot's size the view is once it's framed
+const MINIBROT_FRAME_FACT: f64 = 2.0;
+
 pub struct SfmlEngineInternal<'a> {
     notif_rx: &'a Receiver<FractalNotif>,
     ctx_rwl: Arc<RwLock<FractalContext>>,
@@ -33,6 +44,7 @@ pub struct SfmlEngineInternal<'a> {
     render_texture: FBox<RenderTexture>,
     shader: FBox<Shader<'a>>,
     backend: FractalBackend,
+    buddha_hist: Vec<u64>, // merged histograms of every pass, 3 channels per pixel
 }
 
 struct SfmlEngineWorkerExternal {
@@ -42,14 +54,23 @@ struct SfmlEngineWorkerExternal {
 
 pub enum WorkerNotif {
     SetRenderRect(Rect<u32>),
+    SetReferenceOrbit(Arc<ReferenceOrbit>),
     Reload(FractalBackend),
     Shutdown,
 }
 
 pub struct WorkerResult {
-    pub pixels: Vec<u8>,
-    pub rrect: Rect<u32>,
+    pub output: WorkerOutput,
     pub reload_dur: Duration,
+    pub short_circuited: u32,
+}
+
+pub enum WorkerOutput {
+    // Finished RGBA rows of the worker's RenderRect
+    Pixels { pixels: Vec<u8>, rrect: Rect<u32> },
+    // Hits on the whole texture, 3 channels per pixel, merged with the other
+    // workers' and the previous passes'
+    Histogram(Vec<u32>),
 }
 
 impl<'a> SfmlEngineInternal<'a> {
@@ -127,6 +148,7 @@ impl<'a> SfmlEngineInternal<'a> {
                 workers,
                 shader,
                 backend,
+                buddha_hist: Vec::new(),
             };
 
             internal_engine.run_until_stop();
@@ -137,6 +159,7 @@ impl<'a> SfmlEngineInternal<'a> {
         while self.win.is_open() {
             self.handle_events_internal();
             self.handle_notifs_internal();
+            self.accumulate_buddhabrot_if_needed();
             self.render_internal();
         }
     }
@@ -148,7 +171,15 @@ impl<'a> SfmlEngineInternal<'a> {
                 Event::Resized { width, height } => self.resize_internal(width, height),
                 Event::MouseButtonReleased { x, y, button } => {
                     if button == Button::Left {
-                        self.move_window_from_mouse_pos(x, y);
+                        let ctrl = Key::LControl.is_pressed() || Key::RControl.is_pressed();
+                        let shift = Key::LShift.is_pressed() || Key::RShift.is_pressed();
+                        let alt = Key::LAlt.is_pressed() || Key::RAlt.is_pressed();
+                        match (ctrl, shift, alt) {
+                            (true, _, _) => self.pick_julia_c_from_mouse_pos(x, y),
+                            (false, true, _) => self.locate_minibrot_from_mouse_pos(x, y),
+                            (false, false, true) => self.locate_misiurewicz_from_mouse_pos(x, y),
+                            (false, false, false) => self.move_window_from_mouse_pos(x, y),
+                        }
                     }
                 }
                 Event::MouseWheelScrolled {
@@ -174,6 +205,7 @@ impl<'a> SfmlEngineInternal<'a> {
                 FractalNotif::Commence => panic!("bah bro je roule déjà..."),
                 FractalNotif::Shutdown => self.shutdown_internal(),
                 FractalNotif::Reload(backend) => {
+                    let backend = self.ctx_rwl.read().unwrap().supported_backend(backend);
                     self.backend = backend;
                     self.reload_internal(backend);
                 }
@@ -187,7 +219,11 @@ impl<'a> SfmlEngineInternal<'a> {
         let mut sprite;
 
         match self.backend {
-            FractalBackend::F64 | FractalBackend::Rug => {
+            FractalBackend::F32
+            | FractalBackend::F64
+            | FractalBackend::DoubleDouble
+            | FractalBackend::Rug
+            | FractalBackend::Perturbation => {
                 sprite = Sprite::with_texture(&self.texture);
             }
             FractalBackend::Shader => {
@@ -221,6 +257,7 @@ impl<'a> SfmlEngineInternal<'a> {
         let mut new_real = ctx.window.real().clone();
         new_real.mul_from(height as f64 / width as f64);
         ctx.window.mut_imag().assign(new_real);
+        ctx.adjust_auto_prec();
 
         self.win.set_view(
             &*View::from_rect(FloatRect::new(0.0, 0.0, width as f32, height as f32)).unwrap(),
@@ -232,6 +269,90 @@ impl<'a> SfmlEngineInternal<'a> {
 
         ctx.center =
             fractal_complex::map_pixel_value_rug(self.win.size(), &ctx.center, &ctx.window, (x, y));
+        ctx.adjust_auto_prec();
+
+        drop(ctx);
+        self.reload_internal(self.backend);
+    }
+
+    // Shows the whole Julia set of the point under the mouse
+    fn pick_julia_c_from_mouse_pos(&mut self, x: i32, y: i32) {
+        let mut ctx = self.ctx_rwl.write().unwrap();
+
+        ctx.julia_c =
+            fractal_complex::map_pixel_value_rug(self.win.size(), &ctx.center, &ctx.window, (x, y));
+        ctx.julia = true;
+        ctx.center = ctx.base_center();
+        ctx.reset_window();
+
+        drop(ctx);
+        self.reload_internal(self.backend);
+    }
+
+    // Frames the minibrot of lowest period around the point under the mouse
+    // Only the z² + c Mandelbrot set has its minibrots found
+    fn locate_minibrot_from_mouse_pos(&mut self, x: i32, y: i32) {
+        let ctx = self.ctx_rwl.read().unwrap();
+        if ctx.mode != FractalMode::Escape || ctx.julia || ctx.formula != FractalFormula::MANDELBROT
+        {
+            return;
+        }
+
+        let c =
+            fractal_complex::map_pixel_value_rug(self.win.size(), &ctx.center, &ctx.window, (x, y));
+        let mut radius = ctx.window.real().clone();
+        radius *= FINDER_SEARCH_FACT;
+        let seq_iter = ctx.seq_iter;
+        // The search can take a while, the workers and the GUI keep going
+        drop(ctx);
+        let Some(minibrot) = Minibrot::find(&c, &radius, seq_iter) else {
+            return;
+        };
+
+        let mut ctx = self.ctx_rwl.write().unwrap();
+        ctx.center = minibrot.nucleus;
+        ctx.minibrot_period = Some(minibrot.period);
+        let mut new_real = ctx.window.real().clone();
+        new_real.assign(&minibrot.size);
+        new_real *= BASE_WINDOW.0 * MINIBROT_FRAME_FACT;
+        // The size estimate is only right for minibrots, not for the bulbs,
+        // it never zooms out
+        if new_real < *ctx.window.real() {
+            ctx.window.mut_real().assign(&new_real);
+            new_real *= ctx.res.y as f64 / ctx.res.x as f64;
+            ctx.window.mut_imag().assign(new_real);
+        }
+        if ctx.auto_seq_iter {
+            ctx.seq_iter = fractal_engine::seq_iters_formula(&ctx.window, ctx.auto_seq_iter_fact);
+        }
+        ctx.adjust_auto_prec();
+
+        drop(ctx);
+        self.reload_internal(self.backend);
+    }
+
+    // Moves to the Misiurewicz point closest to the mouse, the zoom doesn't change
+    fn locate_misiurewicz_from_mouse_pos(&mut self, x: i32, y: i32) {
+        let ctx = self.ctx_rwl.read().unwrap();
+        if ctx.mode != FractalMode::Escape || ctx.julia || ctx.formula != FractalFormula::MANDELBROT
+        {
+            return;
+        }
+
+        let c =
+            fractal_complex::map_pixel_value_rug(self.win.size(), &ctx.center, &ctx.window, (x, y));
+        let mut radius = ctx.window.real().clone();
+        radius *= FINDER_SEARCH_FACT;
+        // Same as the minibrots, the lock isn't held during the search
+        drop(ctx);
+        let Some(misiurewicz) = Misiurewicz::find(&c, &radius) else {
+            return;
+        };
+
+        let mut ctx = self.ctx_rwl.write().unwrap();
+        ctx.center.assign(&misiurewicz.point);
+        ctx.misiurewicz = Some(misiurewicz);
+        ctx.adjust_auto_prec();
 
         drop(ctx);
         self.reload_internal(self.backend);
@@ -254,6 +375,7 @@ impl<'a> SfmlEngineInternal<'a> {
         if ctx.auto_seq_iter {
             ctx.seq_iter = fractal_engine::seq_iters_formula(&ctx.window, ctx.auto_seq_iter_fact);
         }
+        ctx.adjust_auto_prec();
         drop(ctx);
 
         self.reload_internal(self.backend);
@@ -273,6 +395,7 @@ impl<'a> SfmlEngineInternal<'a> {
                     .unwrap();
                 self.workers.pop().unwrap();
                 ctx.reload_durs.pop().unwrap();
+                ctx.short_circuited.pop().unwrap();
             }
         } else if new_worker_count > self.workers.len() {
             for id in 0..(new_worker_count - self.workers.len()) {
@@ -296,6 +419,7 @@ impl<'a> SfmlEngineInternal<'a> {
                     rx: worker_rx,
                 });
                 ctx.reload_durs.push(Duration::ZERO);
+                ctx.short_circuited.push(0);
             }
         }
     }
@@ -321,6 +445,21 @@ impl<'a> SfmlEngineInternal<'a> {
         }
     }
 
+    fn send_reference_orbit_workers(&mut self) {
+        let ctx = self.ctx_rwl.read().unwrap();
+        let ref_orbit = Arc::new(ReferenceOrbit::compute(&ctx));
+        drop(ctx);
+
+        self.ctx_rwl.write().unwrap().skipped_iters = ref_orbit.series.skipped_iters();
+
+        for worker in &self.workers {
+            worker
+                .tx
+                .send(WorkerNotif::SetReferenceOrbit(Arc::clone(&ref_orbit)))
+                .unwrap();
+        }
+    }
+
     fn adjust_workers_if_needed(&mut self) {
         let ctx_worker_count = self.ctx_rwl.read().unwrap().worker_count;
 
@@ -397,13 +536,105 @@ impl<'a> SfmlEngineInternal<'a> {
         self.shader
             .set_uniform_float("u_ConvergeDistance", ctx.converge_distance as f32)
             .unwrap();
+
+        self.shader
+            .set_uniform_bool("u_SmoothColoring", ctx.smooth_coloring)
+            .unwrap();
+
+        self.shader
+            .set_uniform_bool("u_InteriorCheck", ctx.skips_interior())
+            .unwrap();
+
+        self.shader
+            .set_uniform_float("u_PeriodicityEpsilon", ctx.periodicity_epsilon() as f32)
+            .unwrap();
+
+        let (escape_norm, norm_weights) = match ctx.escape_norm {
+            EscapeNorm::L1 => (0, (1.0, 1.0)),
+            EscapeNorm::Euclidean => (1, (1.0, 1.0)),
+            EscapeNorm::LInf => (2, (1.0, 1.0)),
+            EscapeNorm::Weighted(w_re, w_im) => (3, (w_re as f32, w_im as f32)),
+        };
+        self.shader
+            .set_uniform_int("u_EscapeNorm", escape_norm)
+            .unwrap();
+        self.shader
+            .set_uniform_vec2("u_NormWeights", norm_weights.into())
+            .unwrap();
+
+        let coloring_mode = match ctx.coloring_mode {
+            ColoringMode::Iteration => 0,
+            ColoringMode::DistanceBoundary => 1,
+            ColoringMode::DistanceDarkened => 2,
+            ColoringMode::OrbitTrap => 3,
+            ColoringMode::StripeAverage => 4,
+            ColoringMode::TriangleAverage => 5,
+            ColoringMode::CurvatureAverage => 6,
+        };
+        self.shader
+            .set_uniform_int("u_ColoringMode", coloring_mode)
+            .unwrap();
+
+        let trap_shape = match ctx.orbit_trap.shape {
+            TrapShape::Point => 0,
+            TrapShape::Line => 1,
+            TrapShape::Cross => 2,
+            TrapShape::Circle => 3,
+        };
+        self.shader
+            .set_uniform_int("u_TrapShape", trap_shape)
+            .unwrap();
+        self.shader
+            .set_uniform_vec2(
+                "u_TrapCenter",
+                Vector2f::new(
+                    ctx.orbit_trap.center.re as f32,
+                    ctx.orbit_trap.center.im as f32,
+                ),
+            )
+            .unwrap();
+        self.shader
+            .set_uniform_float("u_TrapRadius", ctx.orbit_trap.radius as f32)
+            .unwrap();
+        self.shader
+            .set_uniform_float("u_TrapAngle", ctx.orbit_trap.angle as f32)
+            .unwrap();
+
+        self.shader
+            .set_uniform_float("u_PixelSize", ctx.pixel_size() as f32)
+            .unwrap();
+
+        let formula = match ctx.formula {
+            FractalFormula::Multibrot(_) => 0,
+            FractalFormula::BurningShip => 1,
+            FractalFormula::Tricorn => 2,
+            FractalFormula::Celtic => 3,
+        };
+        self.shader.set_uniform_int("u_Formula", formula).unwrap();
+        self.shader
+            .set_uniform_int("u_Power", ctx.formula.power() as i32)
+            .unwrap();
+
+        self.shader.set_uniform_bool("u_Julia", ctx.julia).unwrap();
+
+        self.shader
+            .set_uniform_vec4(
+                "u_JuliaC",
+                fractal_engine::two_f64_to_vec4(
+                    ctx.julia_c.real().to_f64(),
+                    ctx.julia_c.imag().to_f64(),
+                ),
+            )
+            .unwrap();
     }
 
     fn reload_internal(&mut self, backend: FractalBackend) {
         match backend {
-            FractalBackend::F64 | FractalBackend::Rug => {
-                self.prepare_and_reload_internal_cpu(backend)
-            }
+            FractalBackend::F32
+            | FractalBackend::F64
+            | FractalBackend::DoubleDouble
+            | FractalBackend::Rug
+            | FractalBackend::Perturbation => self.prepare_and_reload_internal_cpu(backend),
             FractalBackend::Shader => self.prepare_and_reload_internal_gpu(),
         }
     }
@@ -412,30 +643,83 @@ impl<'a> SfmlEngineInternal<'a> {
         // Prepare
         self.adjust_workers_if_needed();
         self.adjust_textures_if_needed();
+        if backend == FractalBackend::Perturbation {
+            self.send_reference_orbit_workers();
+        }
+        // The old orbits don't land at the same place anymore
+        self.buddha_hist.clear();
+        self.ctx_rwl.write().unwrap().buddha_passes = 0;
 
         // Reload
+        self.run_workers(backend);
+    }
+
+    fn run_workers(&mut self, backend: FractalBackend) {
         // Send the start message to the workers !
         for worker in &self.workers {
             worker.tx.send(WorkerNotif::Reload(backend)).unwrap();
         }
 
         // Receive raw pixel data and upload it to GPU
+        let mut got_histogram = false;
         for (id, worker) in self.workers.iter().enumerate() {
             let WorkerResult {
-                pixels,
-                rrect,
+                output,
                 reload_dur,
+                short_circuited,
             } = worker.rx.recv().unwrap();
 
-            self.ctx_rwl.write().unwrap().reload_durs[id] = reload_dur;
+            let mut ctx = self.ctx_rwl.write().unwrap();
+            ctx.reload_durs[id] = reload_dur;
+            ctx.short_circuited[id] = short_circuited;
+            drop(ctx);
+
+            match output {
+                WorkerOutput::Pixels { pixels, rrect } => self.texture.update_from_pixels(
+                    &*pixels,
+                    rrect.width,
+                    rrect.height,
+                    rrect.left,
+                    rrect.top,
+                ),
+                WorkerOutput::Histogram(hist) => {
+                    got_histogram = true;
+                    // u64, the passes add up past what u32 holds on the hot pixels
+                    if self.buddha_hist.len() != hist.len() {
+                        self.buddha_hist = hist.into_iter().map(u64::from).collect();
+                    } else {
+                        for (total, count) in self.buddha_hist.iter_mut().zip(hist) {
+                            *total += count as u64;
+                        }
+                    }
+                }
+            }
+        }
+
+        if got_histogram {
+            self.ctx_rwl.write().unwrap().buddha_passes += 1;
+            let pixels = fractal_buddhabrot::tone_map(&self.buddha_hist);
+            let size = self.texture.size();
+            // A resize while the workers were sampling, the next reload will fix it
+            if pixels.len() == (size.x * size.y * 4) as usize {
+                self.texture
+                    .update_from_pixels(&pixels, size.x, size.y, 0, 0);
+            }
+        }
+    }
+
+    // The Buddhabrot keeps getting more samples after the reload, until it
+    // has done enough passes
+    fn accumulate_buddhabrot_if_needed(&mut self) {
+        let ctx = self.ctx_rwl.read().unwrap();
+        let needed = ctx.mode == FractalMode::Buddhabrot
+            && ctx.buddha_passes > 0
+            && ctx.buddha_passes < ctx.buddha_max_passes;
+        drop(ctx);
 
-            self.texture.update_from_pixels(
-                &*pixels,
-                rrect.width,
-                rrect.height,
-                rrect.left,
-                rrect.top,
-            );
+        if needed && self.backend == FractalBackend::F64 {
+            self.adjust_textures_if_needed();
+            self.run_workers(FractalBackend::F64);
         }
     }
 
@@ -3,22 +3,36 @@ use std::{
         Arc, RwLock,
         mpsc::{Receiver, Sender},
     },
-    time::Instant,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
 use sfml::graphics::Rect;
 
 use crate::{
-    fractal_complex::{self, Complex},
-    fractal_engine::{FractalBackend, FractalContext},
-    sfml_engine_internal::{WorkerNotif, WorkerResult},
+    fractal_buddhabrot::{BuddhaView, XorShift},
+    fractal_coloring::{Coloring, OrbitStats},
+    fractal_complex::{self, Complex, FractalFloat, Periodicity},
+    fractal_double_double::DoubleDouble,
+    fractal_engine::{FractalBackend, FractalContext, FractalMode},
+    fractal_expr::StartZ,
+    fractal_fixed::Fixed,
+    fractal_floatexp::FloatExp,
+    fractal_lyapunov,
+    fractal_newton::NewtonPolynomial,
+    fractal_perturbation::ReferenceOrbit,
+    fractal_simd::{LANES, SimdKernel},
+    sfml_engine_internal::{WorkerNotif, WorkerOutput, WorkerResult},
 };
 
+// Binary exponent of the window under which the perturbation uses FloatExp deltas
+const FLOATEXP_WINDOW_EXP: i32 = -1000;
+
 pub struct SfmlEngineWorkerInternal {
     notif_rx: Receiver<WorkerNotif>,
     data_tx: Sender<WorkerResult>,
     ctx_rwl: Arc<RwLock<FractalContext>>,
     rrect: Rect<u32>,
+    ref_orbit: Arc<ReferenceOrbit>,
 }
 
 impl SfmlEngineWorkerInternal {
@@ -32,6 +46,7 @@ impl SfmlEngineWorkerInternal {
             notif_rx,
             data_tx,
             rrect: Rect::<u32>::default(),
+            ref_orbit: Arc::default(),
         };
 
         worker.run()
@@ -46,108 +61,487 @@ impl SfmlEngineWorkerInternal {
                 }
                 WorkerNotif::Shutdown => break,
                 WorkerNotif::SetRenderRect(render_rect) => self.rrect = render_rect,
+                WorkerNotif::SetReferenceOrbit(ref_orbit) => self.ref_orbit = ref_orbit,
             }
         }
     }
 
     fn choose_compute_backend(&mut self, backend: FractalBackend) -> WorkerResult {
-        match backend {
-            FractalBackend::F64 => self.compute_image_f64(),
-            FractalBackend::Rug => self.compute_image_rug(),
-            FractalBackend::Shader => panic!("Bro I am not supposed to handle that"),
+        let ctx = self.ctx_rwl.read().unwrap();
+        let (mode, f32_is_enough) = (ctx.mode, ctx.f32_is_enough());
+        let fixed_point_limbs = ctx.fixed_point_limbs();
+        drop(ctx);
+        match (mode, backend) {
+            (_, FractalBackend::Shader) => panic!("Bro I am not supposed to handle that"),
+            (FractalMode::Escape, FractalBackend::F32) if f32_is_enough => {
+                self.compute_image_escape::<f32>()
+            }
+            (FractalMode::Escape, FractalBackend::F32 | FractalBackend::F64) => {
+                self.compute_image_f64()
+            }
+            (FractalMode::Escape, FractalBackend::DoubleDouble) => {
+                self.compute_image_escape::<DoubleDouble>()
+            }
+            (FractalMode::Escape, FractalBackend::Rug) => match fixed_point_limbs {
+                // The limb count has to be known at compile time
+                Some(2) => self.compute_image_escape::<Fixed<2>>(),
+                Some(3) => self.compute_image_escape::<Fixed<3>>(),
+                Some(4) => self.compute_image_escape::<Fixed<4>>(),
+                Some(5) => self.compute_image_escape::<Fixed<5>>(),
+                Some(6) => self.compute_image_escape::<Fixed<6>>(),
+                Some(7) => self.compute_image_escape::<Fixed<7>>(),
+                Some(8) => self.compute_image_escape::<Fixed<8>>(),
+                _ => self.compute_image_escape::<rug::Float>(),
+            },
+            (FractalMode::Escape, FractalBackend::Perturbation) => {
+                self.compute_image_perturbation()
+            }
+            (FractalMode::Newton, FractalBackend::F64) => self.compute_newton_f64(),
+            (FractalMode::Newton, FractalBackend::Rug) => self.compute_newton_rug(),
+            (FractalMode::Buddhabrot, FractalBackend::F64) => self.compute_buddhabrot(),
+            (FractalMode::Lyapunov, FractalBackend::F64) => self.compute_lyapunov(),
+            (FractalMode::Custom, FractalBackend::F64) => self.compute_custom_f64(),
+            (FractalMode::Custom, FractalBackend::Rug) => self.compute_custom_rug(),
+            (
+                FractalMode::Newton
+                | FractalMode::Buddhabrot
+                | FractalMode::Lyapunov
+                | FractalMode::Custom,
+                _,
+            ) => {
+                panic!("FractalContext::supported_backend is not used")
+            }
         }
     }
 
-    fn compute_image_f64(&mut self) -> WorkerResult {
+    // Every pixel of the RenderRect row by row, colored from its coordinates
+    // in the whole texture
+    fn pixel_loop(&self, mut color: impl FnMut(u32, u32) -> [u8; 4]) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity((self.rrect.width * self.rrect.height * 4) as usize);
+        for y in 0..self.rrect.height {
+            for x in 0..self.rrect.width {
+                pixels.extend_from_slice(&color(self.rrect.left + x, self.rrect.top + y));
+            }
+        }
+        pixels
+    }
+
+    fn pixels_result(&self, pixels: Vec<u8>, start: Instant, short_circuited: u32) -> WorkerResult {
+        WorkerResult {
+            output: WorkerOutput::Pixels {
+                pixels,
+                rrect: self.rrect,
+            },
+            reload_dur: start.elapsed(),
+            short_circuited,
+        }
+    }
+
+    fn compute_image_escape<T: FractalFloat>(&mut self) -> WorkerResult {
+        let formula = self.ctx_rwl.read().unwrap().formula;
+        self.escape_loop::<T>(StartZ::Pixel, |z, c, scratch| {
+            z.iterate(c, formula, scratch)
+        })
+    }
+
+    // The expression tree is walked for every iteration
+    fn compute_custom_f64(&mut self) -> WorkerResult {
+        let ctx = self.ctx_rwl.read().unwrap();
+        let (expr, start_z) = (ctx.custom_expr.clone(), ctx.custom_start);
+        drop(ctx);
+        self.escape_loop::<f64>(start_z, |z, c, _| *z = expr.eval_f64(*z, *c))
+    }
+
+    // Same, every node of the tree allocates its result
+    fn compute_custom_rug(&mut self) -> WorkerResult {
+        let ctx = self.ctx_rwl.read().unwrap();
+        let (expr, start_z) = (ctx.custom_expr.clone(), ctx.custom_start);
+        drop(ctx);
+        self.escape_loop::<rug::Float>(start_z, |z, c, _| *z = expr.eval_rug(z, c))
+    }
+
+    // The escape loop of every CPU backend but the perturbation, T is what z
+    // and c are iterated with and step is one iteration. The Julia sets always
+    // start at the pixel
+    fn escape_loop<T: FractalFloat>(
+        &mut self,
+        start_z: StartZ,
+        mut step: impl FnMut(&mut Complex<T>, &Complex<T>, &mut T::Scratch),
+    ) -> WorkerResult {
         let start = Instant::now();
 
         let ctx = self.ctx_rwl.read().unwrap().clone();
-        let center_c64 = Complex::new(ctx.center.real().to_f64(), ctx.center.imag().to_f64());
-        let window_c64 = Complex::new(ctx.window.real().to_f64(), ctx.window.imag().to_f64());
+        // The center and julia_c can have more bits than the working precision
+        let prec = ctx.window.prec();
+        let center = Complex::<T>::from_rug(&rug::Complex::with_val(prec, &ctx.center));
+        let window = Complex::<T>::from_rug(&ctx.window);
+        let julia_c = Complex::<T>::from_rug(&rug::Complex::with_val(prec, &ctx.julia_c));
         let res_lodiv_c64 = Complex::new(
             (ctx.res.x / ctx.lodiv) as f64,
             (ctx.res.y / ctx.lodiv) as f64,
         );
         let seq_iter = ctx.seq_iter;
-        let converge_distance = ctx.converge_distance;
+        let coloring = Coloring::from_ctx(&ctx);
+        let julia = ctx.julia;
+        let from_zero = start_z == StartZ::Zero && !julia;
+        let zero = Complex::<T>::from_rug(&rug::Complex::new(prec));
+        // Custom shares the loop but not the formula
+        let cardioid_check = ctx.mode == FractalMode::Escape && ctx.cardioid_check();
+        let track_orbit = coloring.tracks_orbit();
+        let escape_norm = ctx.escape_norm;
+        let bailout = ctx.bailout();
+        let interior_check = ctx.skips_interior();
+        let periodicity_epsilon = ctx.periodicity_epsilon();
+        let mut scratch = T::scratch(prec.0);
         drop(ctx);
 
+        // Written over for every pixel, nothing gets allocated in the loop
+        let mut pixel = center.clone();
+        let mut c = julia_c.clone();
+        let mut n = center.clone();
+
         let mut pixels = Vec::with_capacity((self.rrect.width * self.rrect.height * 4) as usize);
+        let mut short_circuited = 0;
 
         for y in 0..self.rrect.height {
             for x in 0..self.rrect.width {
-                let c = Complex::map_pixel_value_f64(
+                pixel.map_pixel_value(
                     res_lodiv_c64,
-                    center_c64,
-                    window_c64,
+                    &center,
+                    &window,
                     Complex::new((self.rrect.left + x) as f64, (self.rrect.top + y) as f64),
                 );
-                let mut n = c;
+                if !julia {
+                    c.assign_from(&pixel);
+                }
+                let c_c64 = c.to_f64();
+                if cardioid_check && c_c64.in_cardioid_or_bulb_f64() {
+                    short_circuited += 1;
+                    pixels.extend_from_slice(&[0, 0, 0, 255]);
+                    continue;
+                }
+                let mut periodicity = Periodicity::new(periodicity_epsilon);
+                let (z0, mut stats) = match from_zero {
+                    // z_0 doesn't depend on c, dz/dc starts at 0
+                    true => (
+                        &zero,
+                        OrbitStats::starting_at(
+                            Complex::default(),
+                            Complex::default(),
+                            c_c64,
+                            &coloring,
+                        ),
+                    ),
+                    false => (&pixel, OrbitStats::new(pixel.to_f64(), c_c64, &coloring)),
+                };
+                n.assign_from(z0);
                 let mut distance = 0.0;
                 let mut iter = 0;
-                while iter < seq_iter && distance <= converge_distance {
-                    n.f_sq_add_f64(c);
-                    distance = n.abs_sum_f64();
+                while iter < seq_iter && distance <= bailout {
+                    step(&mut n, &c, &mut scratch);
+                    // Only the escape check, f64 is enough for it
+                    let z = n.to_f64();
+                    distance = z.distance_f64(escape_norm);
                     iter += 1;
+                    if track_orbit {
+                        stats.update(z);
+                    }
+                    if interior_check && distance <= bailout && periodicity.check(z) {
+                        short_circuited += 1;
+                        break;
+                    }
                 }
-                if distance <= converge_distance {
-                    pixels.extend_from_slice(&[0, 0, 0, 255]);
+                if distance <= bailout {
+                    pixels.extend_from_slice(&coloring.interior(&stats));
                 } else {
-                    let color = fractal_complex::iter_gradient(iter, seq_iter);
-                    pixels.extend_from_slice(&color);
+                    pixels.extend_from_slice(&coloring.escaped(iter, distance, &stats));
                 }
             }
         }
 
-        WorkerResult {
-            pixels,
-            rrect: self.rrect,
-            reload_dur: start.elapsed(),
+        self.pixels_result(pixels, start, short_circuited)
+    }
+
+    // 4 pixels at a time when the CPU and the context allow it
+    fn compute_image_f64(&mut self) -> WorkerResult {
+        let start = Instant::now();
+
+        let ctx = self.ctx_rwl.read().unwrap().clone();
+        let coloring = Coloring::from_ctx(&ctx);
+        if !SimdKernel::supported(ctx.formula, ctx.escape_norm, &coloring) {
+            drop(ctx);
+            return self.compute_image_escape::<f64>();
+        }
+        let center_c64 = Complex::new(ctx.center.real().to_f64(), ctx.center.imag().to_f64());
+        let window_c64 = Complex::new(ctx.window.real().to_f64(), ctx.window.imag().to_f64());
+        let julia_c_c64 = Complex::from_rug_f64(&ctx.julia_c);
+        let res_lodiv_c64 = Complex::new(
+            (ctx.res.x / ctx.lodiv) as f64,
+            (ctx.res.y / ctx.lodiv) as f64,
+        );
+        let julia = ctx.julia;
+        let cardioid_check = ctx.cardioid_check();
+        let bailout = ctx.bailout();
+        let kernel = SimdKernel {
+            formula: ctx.formula,
+            seq_iter: ctx.seq_iter,
+            bailout,
+            periodicity_epsilon: ctx.skips_interior().then_some(ctx.periodicity_epsilon()),
+        };
+        drop(ctx);
+
+        let mut pixels = Vec::with_capacity((self.rrect.width * self.rrect.height * 4) as usize);
+        let mut short_circuited = 0;
+
+        for y in 0..self.rrect.height {
+            for x in (0..self.rrect.width).step_by(LANES) {
+                // The lanes past the end of the row are skipped
+                let lane_count = (self.rrect.width - x).min(LANES as u32) as usize;
+                let mut z = [Complex::default(); LANES];
+                let mut c = [Complex::default(); LANES];
+                let mut skip = [true; LANES];
+                for lane in 0..lane_count {
+                    z[lane] = Complex::map_pixel_value_f64(
+                        res_lodiv_c64,
+                        center_c64,
+                        window_c64,
+                        Complex::new(
+                            (self.rrect.left + x + lane as u32) as f64,
+                            (self.rrect.top + y) as f64,
+                        ),
+                    );
+                    c[lane] = match julia {
+                        true => julia_c_c64,
+                        false => z[lane],
+                    };
+                    skip[lane] = cardioid_check && c[lane].in_cardioid_or_bulb_f64();
+                }
+
+                let orbits = kernel.iterate(z, c, skip);
+                for lane in 0..lane_count {
+                    let stats = OrbitStats::new(z[lane], c[lane], &coloring);
+                    if skip[lane] || orbits.periodic[lane] {
+                        short_circuited += 1;
+                    }
+                    if orbits.distance[lane] <= bailout {
+                        pixels.extend_from_slice(&coloring.interior(&stats));
+                    } else {
+                        pixels.extend_from_slice(&coloring.escaped(
+                            orbits.iter[lane],
+                            orbits.distance[lane],
+                            &stats,
+                        ));
+                    }
+                }
+            }
         }
+
+        self.pixels_result(pixels, start, short_circuited)
     }
 
-    fn compute_image_rug(&mut self) -> WorkerResult {
+    fn compute_image_perturbation(&mut self) -> WorkerResult {
         let start = Instant::now();
 
         let ctx = self.ctx_rwl.read().unwrap().clone();
-        let center = ctx.center.clone();
-        let window = ctx.window.clone();
-        let res = ctx.res / ctx.lodiv;
-        let seq_iter = ctx.seq_iter;
-        let converge_distance = ctx.converge_distance;
+        let center_c64 = Complex::from_rug_f64(&ctx.center);
+        let window_c64 = Complex::new(ctx.window.real().to_f64(), ctx.window.imag().to_f64());
+        let window_cfe = Complex::from_rug_floatexp(&ctx.window);
+        // f64 can't hold the pixel offsets anymore, FloatExp can
+        let use_floatexp = ctx.window.real().get_exp().unwrap_or(0) < FLOATEXP_WINDOW_EXP;
+        let res_lodiv_c64 = Complex::new(
+            (ctx.res.x / ctx.lodiv) as f64,
+            (ctx.res.y / ctx.lodiv) as f64,
+        );
+        let coloring = Coloring::from_ctx(&ctx);
+        let cardioid_check = ctx.cardioid_check();
+        let bailout = ctx.bailout();
         drop(ctx);
 
         let mut pixels = Vec::with_capacity((self.rrect.width * self.rrect.height * 4) as usize);
+        let mut short_circuited = 0;
 
         for y in 0..self.rrect.height {
             for x in 0..self.rrect.width {
-                let c = fractal_complex::map_pixel_value_rug(
-                    res,
-                    &center,
-                    &window,
-                    ((self.rrect.left + x) as i32, (self.rrect.top + y) as i32),
+                // Offset from the center, the reference orbit is computed there
+                let coord = Complex::new((self.rrect.left + x) as f64, (self.rrect.top + y) as f64);
+                let dc = Complex::map_pixel_value_f64(
+                    res_lodiv_c64,
+                    Complex::new(0.0, 0.0),
+                    window_c64,
+                    coord,
                 );
-                let mut n = c.clone();
-                let mut distance = 0.0;
-                let mut iter = 0;
-                while iter < seq_iter && distance <= converge_distance {
-                    n = fractal_complex::f_sq_add_rug(&n, &c);
-                    distance = fractal_complex::abs_sum_rug(&n);
-                    iter += 1;
-                }
-                if distance <= converge_distance {
+                // No periodicity check here, z in f64 is not precise enough at these zooms
+                if cardioid_check && (center_c64 + dc).in_cardioid_or_bulb_f64() {
+                    short_circuited += 1;
                     pixels.extend_from_slice(&[0, 0, 0, 255]);
+                    continue;
+                }
+                let (iter, distance, stats) = if use_floatexp {
+                    let dc = Complex::map_pixel_value_floatexp(
+                        res_lodiv_c64,
+                        Complex::new(FloatExp::ZERO, FloatExp::ZERO),
+                        window_cfe,
+                        coord,
+                    );
+                    self.ref_orbit.iterate_delta_floatexp(dc)
                 } else {
-                    let color = fractal_complex::iter_gradient(iter, seq_iter);
-                    pixels.extend_from_slice(&color);
+                    self.ref_orbit.iterate_delta(dc)
+                };
+                if distance <= bailout {
+                    pixels.extend_from_slice(&coloring.interior(&stats));
+                } else {
+                    pixels.extend_from_slice(&coloring.escaped(iter, distance, &stats));
+                }
+            }
+        }
+
+        self.pixels_result(pixels, start, short_circuited)
+    }
+
+    fn compute_newton_f64(&mut self) -> WorkerResult {
+        let start = Instant::now();
+
+        let ctx = self.ctx_rwl.read().unwrap().clone();
+        let center_c64 = Complex::from_rug_f64(&ctx.center);
+        let window_c64 = Complex::from_rug_f64(&ctx.window);
+        let res_lodiv_c64 = Complex::new(
+            (ctx.res.x / ctx.lodiv) as f64,
+            (ctx.res.y / ctx.lodiv) as f64,
+        );
+        let seq_iter = ctx.seq_iter;
+        let poly = NewtonPolynomial::new(&ctx.newton_coeffs);
+        drop(ctx);
+
+        let pixels = self.pixel_loop(|x, y| {
+            let z = Complex::map_pixel_value_f64(
+                res_lodiv_c64,
+                center_c64,
+                window_c64,
+                Complex::new(x as f64, y as f64),
+            );
+            poly.color(z, seq_iter)
+        });
+        self.pixels_result(pixels, start, 0)
+    }
+
+    fn compute_newton_rug(&mut self) -> WorkerResult {
+        let start = Instant::now();
+
+        let ctx = self.ctx_rwl.read().unwrap().clone();
+        let center = rug::Complex::with_val(ctx.window.prec(), &ctx.center);
+        let window = ctx.window.clone();
+        let res = ctx.res / ctx.lodiv;
+        let seq_iter = ctx.seq_iter;
+        let poly = NewtonPolynomial::new(&ctx.newton_coeffs);
+        drop(ctx);
+
+        let pixels = self.pixel_loop(|x, y| {
+            let z =
+                fractal_complex::map_pixel_value_rug(res, &center, &window, (x as i32, y as i32));
+            poly.color(z, seq_iter)
+        });
+        self.pixels_result(pixels, start, 0)
+    }
+
+    fn compute_lyapunov(&mut self) -> WorkerResult {
+        let start = Instant::now();
+
+        let ctx = self.ctx_rwl.read().unwrap().clone();
+        let center_c64 = Complex::from_rug_f64(&ctx.center);
+        let window_c64 = Complex::from_rug_f64(&ctx.window);
+        let res_lodiv_c64 = Complex::new(
+            (ctx.res.x / ctx.lodiv) as f64,
+            (ctx.res.y / ctx.lodiv) as f64,
+        );
+        let seq_iter = ctx.seq_iter;
+        let seq = fractal_lyapunov::parse_sequence(&ctx.lyapunov_seq);
+        drop(ctx);
+
+        let pixels = self.pixel_loop(|x, y| {
+            let ab = Complex::map_pixel_value_f64(
+                res_lodiv_c64,
+                center_c64,
+                window_c64,
+                Complex::new(x as f64, y as f64),
+            );
+            let exponent = fractal_lyapunov::lyapunov_exponent(ab, &seq, seq_iter);
+            fractal_lyapunov::lyapunov_color(exponent)
+        });
+
+        self.pixels_result(pixels, start, 0)
+    }
+
+    // Not a RenderRect, every worker samples the whole plane and returns a
+    // histogram of the whole view
+    fn compute_buddhabrot(&mut self) -> WorkerResult {
+        let start = Instant::now();
+
+        let ctx = self.ctx_rwl.read().unwrap().clone();
+        let view = BuddhaView::new(
+            Complex::new(
+                (ctx.res.x / ctx.lodiv) as f64,
+                (ctx.res.y / ctx.lodiv) as f64,
+            ),
+            Complex::from_rug_f64(&ctx.center),
+            Complex::from_rug_f64(&ctx.window),
+        );
+        let buddha_iters = ctx.buddha_iters;
+        let max_iter = buddha_iters.into_iter().max().unwrap();
+        let samples = ctx.buddha_samples;
+        let formula = ctx.formula;
+        let escape_norm = ctx.escape_norm;
+        let bailout = ctx.bailout();
+        let cardioid_check = ctx.cardioid_check();
+        drop(ctx);
+
+        // Different for every worker and every pass
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+            ^ ((self.rrect.top as u64) << 32);
+        let mut rng = XorShift::new(seed);
+
+        let mut hist = vec![0u32; view.pixel_count() * 3];
+        let mut orbit = Vec::with_capacity(max_iter as usize);
+        let mut short_circuited = 0;
+
+        for _ in 0..samples {
+            let c = rng.next_c();
+            // Never escapes, nothing to draw
+            if cardioid_check && c.in_cardioid_or_bulb_f64() {
+                short_circuited += 1;
+                continue;
+            }
+            orbit.clear();
+            let mut n = c;
+            let mut distance = 0.0;
+            while (orbit.len() as u32) < max_iter && distance <= bailout {
+                n.iterate(&c, formula, &mut ());
+                distance = n.distance_f64(escape_norm);
+                orbit.push(n);
+            }
+            if distance <= bailout {
+                continue;
+            }
+            // A channel only gets the orbits that escaped before its limit
+            for (ch, &limit) in buddha_iters.iter().enumerate() {
+                if orbit.len() as u32 > limit {
+                    continue;
+                }
+                for &z in &orbit {
+                    if let Some(id) = view.pixel_index(z) {
+                        // A single pass can have up to u32::MAX samples
+                        hist[id * 3 + ch] = hist[id * 3 + ch].saturating_add(1);
+                    }
                 }
             }
         }
 
         WorkerResult {
-            pixels,
-            rrect: self.rrect,
+            output: WorkerOutput::Histogram(hist),
             reload_dur: start.elapsed(),
+            short_circuited,
         }
     }
 }